@@ -106,6 +106,36 @@ impl ArtifactManager {
             code => Err(anyhow!(format!("Unexpected status code {} != 200", code))),
         }
     }
+
+    /// Returns the object at key, or None if it does not exist
+    #[tracing::instrument(name = "artifact.get_object", level = "debug", skip(self))]
+    pub async fn get_object(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut data = Vec::new();
+        let status_code = self
+            .bucket
+            .get_object_stream(key, &mut data)
+            .await
+            .context(format!("Getting object at key {}", key))?;
+        match status_code {
+            200 => Ok(Some(data)),
+            404 => Ok(None),
+            code => Err(anyhow!(format!("Unexpected status code {} != 200", code))),
+        }
+    }
+
+    #[tracing::instrument(name = "artifact.put_object", level = "debug", skip(self, data))]
+    pub async fn put_object(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut reader = data;
+        let status_code = self
+            .bucket
+            .put_object_stream(&mut reader, key)
+            .await
+            .context(format!("Putting object at key {}", key))?;
+        match status_code {
+            200 => Ok(()),
+            code => Err(anyhow!(format!("Unexpected status code {} != 200", code))),
+        }
+    }
 }
 
 // https://www.reddit.com/r/learnrust/comments/lnewid/comment/go021w2
@@ -39,9 +39,12 @@ pub struct PluginResult {
     pub phase: Phase,
     pub message: String,
     pub outputs: Outputs,
+    /// Progress of the node as `N/M`, the last progress reported by the module if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum Phase {
     Succeeded,
     Failed,
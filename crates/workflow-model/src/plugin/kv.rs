@@ -0,0 +1,148 @@
+//! Key-value store of the plugin, which keeps state between the steps of a workflow. The template
+//! needs the `kv` permission, and `global: true` to access the global scope.
+
+use anyhow::anyhow;
+use std::time::Duration;
+
+#[link(wasm_import_module = "workflow_kv")]
+extern "C" {
+    #[link_name = "get"]
+    fn kv_get(
+        scope: u32,
+        key_ptr: *const u8,
+        key_len: usize,
+        buf_ptr: *mut u8,
+        buf_len: usize,
+        written_ptr: *mut usize,
+    ) -> u32;
+    #[link_name = "set"]
+    fn kv_set(
+        scope: u32,
+        key_ptr: *const u8,
+        key_len: usize,
+        value_ptr: *const u8,
+        value_len: usize,
+        ttl_seconds: u64,
+    ) -> u32;
+    #[link_name = "delete"]
+    fn kv_delete(scope: u32, key_ptr: *const u8, key_len: usize) -> u32;
+    #[link_name = "list"]
+    fn kv_list(
+        scope: u32,
+        prefix_ptr: *const u8,
+        prefix_len: usize,
+        buf_ptr: *mut u8,
+        buf_len: usize,
+        written_ptr: *mut usize,
+    ) -> u32;
+}
+
+const NOT_FOUND: u32 = 1;
+const BUFFER_TOO_SMALL: u32 = 2;
+
+/// Initial size of the buffer that values are read into, it grows to the size of larger values
+const BUFFER_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Scope {
+    /// Entries shared by the steps of the workflow
+    Workflow = 0,
+    /// Entries shared by all workflows
+    Global = 1,
+}
+
+pub fn get(scope: Scope, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let result = read(|buf, written| unsafe {
+        kv_get(
+            scope as u32,
+            key.as_ptr(),
+            key.len(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            written,
+        )
+    });
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(NOT_FOUND) => Ok(None),
+        Err(code) => Err(error(code, "get", key)),
+    }
+}
+
+/// Sets the value of key, which expires after ttl if given
+pub fn set(scope: Scope, key: &str, value: &[u8], ttl: Option<Duration>) -> anyhow::Result<()> {
+    // A TTL of 0 seconds means the entry does not expire
+    let ttl_seconds = ttl.map(|ttl| ttl.as_secs().max(1)).unwrap_or(0);
+    let code = unsafe {
+        kv_set(
+            scope as u32,
+            key.as_ptr(),
+            key.len(),
+            value.as_ptr(),
+            value.len(),
+            ttl_seconds,
+        )
+    };
+    match code {
+        0 => Ok(()),
+        code => Err(error(code, "set", key)),
+    }
+}
+
+pub fn delete(scope: Scope, key: &str) -> anyhow::Result<()> {
+    match unsafe { kv_delete(scope as u32, key.as_ptr(), key.len()) } {
+        0 => Ok(()),
+        code => Err(error(code, "delete", key)),
+    }
+}
+
+/// Returns the keys that start with prefix
+pub fn list(scope: Scope, prefix: &str) -> anyhow::Result<Vec<String>> {
+    let keys = read(|buf, written| unsafe {
+        kv_list(
+            scope as u32,
+            prefix.as_ptr(),
+            prefix.len(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            written,
+        )
+    })
+    .map_err(|code| error(code, "list", prefix))?;
+    let keys = String::from_utf8(keys)?;
+    Ok(keys.lines().map(|key| key.to_owned()).collect())
+}
+
+/// Calls a host function that writes to a buffer, retrying with a buffer of the size it reports
+/// if the buffer was too small
+fn read(call: impl Fn(&mut [u8], *mut usize) -> u32) -> Result<Vec<u8>, u32> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut written = 0;
+    let mut code = call(&mut buf, &mut written);
+    if code == BUFFER_TOO_SMALL {
+        buf = vec![0; written];
+        code = call(&mut buf, &mut written);
+    }
+    match code {
+        0 => {
+            buf.truncate(written);
+            Ok(buf)
+        }
+        code => Err(code),
+    }
+}
+
+fn error(code: u32, operation: &str, key: &str) -> anyhow::Error {
+    let reason = match code {
+        7 => "not allowed",
+        8 => "invalid key",
+        9 => "value too large",
+        10 => "store failed",
+        11 => "invalid ttl",
+        _ => "host error",
+    };
+    anyhow!(format!(
+        "KV {} of {} failed: {} ({})",
+        operation, key, reason, code
+    ))
+}
@@ -0,0 +1,60 @@
+//! Structured logs of the plugin, which the host emits as its own logs and adds to the log
+//! artifact if the template enables it. Use them through the `log!` macro:
+//!
+//! ```ignore
+//! use workflow_model::plugin::log::Level;
+//!
+//! workflow_model::plugin::log!(Level::Info, "Resized image", width = 640, height = 480);
+//! ```
+
+#[link(wasm_import_module = "workflow_log")]
+extern "C" {
+    #[link_name = "log"]
+    fn host_log(
+        level: u32,
+        message_ptr: *const u8,
+        message_len: usize,
+        fields_ptr: *const u8,
+        fields_len: usize,
+    ) -> u32;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+}
+
+/// Emits a log record. Logging never fails the plugin, so errors of the host are ignored.
+pub fn emit(level: Level, message: &str, fields: &[(&str, String)]) {
+    // Fields are passed as lines of `key=value`, so line breaks in values are replaced
+    let fields = fields
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value.replace('\n', " ")))
+        .collect::<String>();
+    unsafe {
+        host_log(
+            level as u32,
+            message.as_ptr(),
+            message.len(),
+            fields.as_ptr(),
+            fields.len(),
+        );
+    }
+}
+
+/// Emits a log record with a message and fields given as `key = value`, where the values
+/// implement Display
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $message:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        $crate::plugin::log::emit(
+            $level,
+            &$message.to_string(),
+            &[$((stringify!($key), $value.to_string())),*],
+        )
+    };
+}
@@ -7,6 +7,15 @@ use std::fs::File;
 use std::path::PathBuf;
 use std::process::exit;
 
+#[cfg(target_arch = "wasm32")]
+pub mod kv;
+#[cfg(target_arch = "wasm32")]
+pub mod log;
+#[cfg(target_arch = "wasm32")]
+pub mod progress;
+#[cfg(target_arch = "wasm32")]
+pub use crate::log;
+
 struct PluginManager {}
 
 impl PluginManager {
@@ -118,6 +127,7 @@ fn wrapper(
                 phase: Phase::Failed,
                 message: format!("{:#}", why),
                 outputs: Default::default(),
+                progress: None,
             };
             plugin_manager.set_result(&error_result)?;
             Ok(())
@@ -0,0 +1,23 @@
+//! Progress of the plugin, which Argo shows on the node as `done/total`. The last reported
+//! progress is kept even if the plugin fails afterwards:
+//!
+//! ```ignore
+//! for (i, image) in images.iter().enumerate() {
+//!     workflow_model::plugin::progress::report(i as u32, images.len() as u32, "Resizing images");
+//!     resize(image)?;
+//! }
+//! ```
+
+#[link(wasm_import_module = "workflow_progress")]
+extern "C" {
+    #[link_name = "report"]
+    fn host_report(done: u32, total: u32, message_ptr: *const u8, message_len: usize) -> u32;
+}
+
+/// Reports that done of total steps are done, with an interim message that may be empty.
+/// Progress that has more steps done than in total is ignored by the host.
+pub fn report(done: u32, total: u32, message: &str) {
+    unsafe {
+        host_report(done, total, message.as_ptr(), message.len());
+    }
+}
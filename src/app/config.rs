@@ -1,3 +1,6 @@
+use crate::app::model::quantity::Quantity;
+use crate::app::model::registry::RegistryCredentialEntry;
+use crate::app::model::signature::SignaturePolicy;
 use anyhow::anyhow;
 use clap::Parser;
 use std::fmt::{Debug, Display, Formatter};
@@ -22,9 +25,134 @@ pub struct Config {
     )]
     pub insecure_oci_registries: Vec<String>,
 
+    /// Path to a Docker config.json with credentials for OCI registries
+    #[clap(long = "registry-auth-file", env = "REGISTRY_AUTH_FILE")]
+    pub registry_auth_file: Option<String>,
+
+    /// Comma-separated list of OCI registry credentials as host=username:password or host=token.
+    /// Tokens are sent as the password of basic auth with the username <token>, bearer tokens are
+    /// not supported.
+    #[clap(
+        long = "registry-credentials",
+        env = "REGISTRY_CREDENTIALS",
+        use_value_delimiter = true
+    )]
+    pub registry_credentials: Vec<RegistryCredentialEntry>,
+
+    /// Comma-separated list of paths to PEM-encoded public keys for verifying module signatures
+    #[clap(
+        long = "signature-public-keys",
+        env = "SIGNATURE_PUBLIC_KEYS",
+        use_value_delimiter = true
+    )]
+    pub signature_public_keys: Vec<String>,
+
+    /// Comma-separated list of signature policies as pattern=enforce|warn|off, first match wins
+    #[clap(
+        long = "signature-policies",
+        env = "SIGNATURE_POLICIES",
+        use_value_delimiter = true
+    )]
+    pub signature_policies: Vec<SignaturePolicy>,
+
+    /// Comma-separated list of host paths that templates may mount into modules
+    #[clap(
+        long = "mount-allowlist",
+        env = "MOUNT_ALLOWLIST",
+        use_value_delimiter = true
+    )]
+    pub mount_allowlist: Vec<String>,
+
+    /// ConfigMap in the plugin namespace that stores the KV entries of modules, kept in memory if absent
+    #[clap(long = "kv-config-map", env = "KV_CONFIG_MAP")]
+    pub kv_config_map: Option<String>,
+
+    /// Maximum number of KV entries kept in memory without --kv-config-map
+    #[clap(
+        long = "kv-memory-max-entries",
+        env = "KV_MEMORY_MAX_ENTRIES",
+        default_value = "10000"
+    )]
+    pub kv_memory_max_entries: usize,
+
+    /// Maximum size of the KV entries kept in memory without --kv-config-map
+    #[clap(
+        long = "kv-memory-max-size",
+        env = "KV_MEMORY_MAX_SIZE",
+        default_value = "64Mi"
+    )]
+    pub kv_memory_max_size: Quantity,
+
     #[clap(long = "fs-cache-dir", env = "FS_CACHE_DIR")]
     pub fs_cache_dir: Option<String>,
 
+    /// Maximum size of the modules in the fs cache (e.g. 10Gi), least recently used ones are evicted first
+    #[clap(long = "fs-cache-max-size", env = "FS_CACHE_MAX_SIZE")]
+    pub fs_cache_max_size: Option<Quantity>,
+
+    /// Seconds after which modules that were not used are evicted from the fs cache
+    #[clap(long = "fs-cache-max-age", env = "FS_CACHE_MAX_AGE")]
+    pub fs_cache_max_age: Option<u64>,
+
+    /// Key prefix of the modules shared by all plugins in the bucket of the artifact repository,
+    /// which requires --argo-controller-configmap. Only the Wasm bytes are shared, unless
+    /// --s3-cache-key-file is set.
+    #[clap(long = "s3-cache-prefix", env = "S3_CACHE_PREFIX")]
+    pub s3_cache_prefix: Option<String>,
+
+    /// Path to a file with a secret key of at least 32 bytes that all plugins share. Precompiled
+    /// modules are then shared through the S3 cache as well, authenticated by an HMAC under this key.
+    #[clap(long = "s3-cache-key-file", env = "S3_CACHE_KEY_FILE")]
+    pub s3_cache_key_file: Option<String>,
+
+    /// Seconds between the purges of the fs cache
+    #[clap(
+        long = "fs-cache-purge-interval",
+        env = "FS_CACHE_PURGE_INTERVAL",
+        default_value_t = 300
+    )]
+    pub fs_cache_purge_interval: u64,
+
+    /// Comma-separated list of OCI images that are pulled and compiled at startup
+    #[clap(
+        long = "prefetch-modules",
+        env = "PREFETCH_MODULES",
+        use_value_delimiter = true
+    )]
+    pub prefetch_modules: Vec<String>,
+
+    /// ConfigMap in the plugin namespace that lists OCI images to prefetch in its key `modules`, one per line
+    #[clap(long = "prefetch-config-map", env = "PREFETCH_CONFIG_MAP")]
+    pub prefetch_config_map: Option<String>,
+
+    /// Number of compiled modules kept in memory across requests, 0 disables the in-memory cache
+    #[clap(
+        long = "module-cache-size",
+        env = "MODULE_CACHE_SIZE",
+        default_value_t = 32
+    )]
+    pub module_cache_size: usize,
+
+    /// Enables the pooling instance allocator with slots for the given number of instances
+    #[clap(long = "pooling-instances", env = "POOLING_INSTANCES")]
+    pub pooling_instances: Option<u32>,
+
+    /// Linear memory reserved for each instance slot of the pooling instance allocator (e.g. 1Gi)
+    #[clap(
+        long = "pooling-memory-reservation",
+        env = "POOLING_MEMORY_RESERVATION",
+        default_value = "1Gi"
+    )]
+    pub pooling_memory_reservation: Quantity,
+
+    /// Maximum size of the stdout and stderr of a module returned as output parameters or artifact
+    #[clap(
+        long = "module-log-limit",
+        env = "MODULE_LOG_LIMIT",
+        default_value = "1Mi"
+    )]
+    pub module_log_limit: Quantity,
+
     #[clap(long = "log-level", env = "LOG_LEVEL")]
     log_level: Option<LogLevel>,
 
@@ -61,6 +189,26 @@ pub struct Config {
 
     #[clap(long = "num-concurrent-requests")]
     pub num_concurrent_requests: Option<u16>,
+
+    /// Default fuel budget of a module execution, used if the template does not set one
+    #[clap(long = "wasm-fuel-limit", env = "WASM_FUEL_LIMIT")]
+    pub wasm_fuel_limit: Option<u64>,
+
+    /// Default time limit of a module execution in seconds, used if the template does not set one
+    #[clap(long = "wasm-timeout", env = "WASM_TIMEOUT")]
+    pub wasm_timeout: Option<u64>,
+
+    /// Default memory limit of a module execution (e.g. 256Mi), used if the template does not set one
+    #[clap(long = "wasm-memory-limit", env = "WASM_MEMORY_LIMIT")]
+    pub wasm_memory_limit: Option<Quantity>,
+
+    /// Default limit of table elements of a module execution, used if the template does not set one
+    #[clap(long = "wasm-table-elements-limit", env = "WASM_TABLE_ELEMENTS_LIMIT")]
+    pub wasm_table_elements_limit: Option<u32>,
+
+    /// Default limit of instances of a module execution, used if the template does not set one
+    #[clap(long = "wasm-instances-limit", env = "WASM_INSTANCES_LIMIT")]
+    pub wasm_instances_limit: Option<usize>,
 }
 
 impl Config {
@@ -102,8 +250,9 @@ impl Config {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub enum LogLevel {
+    #[default]
     Info,
     Debug,
     Trace,
@@ -119,12 +268,6 @@ impl LogLevel {
     }
 }
 
-impl Default for LogLevel {
-    fn default() -> Self {
-        LogLevel::Info
-    }
-}
-
 impl Display for LogLevel {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(self.to_str())
@@ -144,8 +287,9 @@ impl FromStr for LogLevel {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum Mode {
+    #[default]
     Local,
     Distributed,
 }
@@ -159,12 +303,6 @@ impl Mode {
     }
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Mode::Local
-    }
-}
-
 impl Display for Mode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(self.to_str())
@@ -1,25 +1,47 @@
 use crate::app::config::{Config, Mode};
 use crate::app::k8s;
+use crate::app::metrics::Metrics;
+use crate::app::model::registry::RegistryCredentials;
+use crate::app::prefetch;
 use crate::app::wasm::distributed::{Config as DistributedConfig, DistributedRunner};
-use crate::app::wasm::local::{cache, LocalRunner};
-use crate::app::wasm::Runner;
+use crate::app::wasm::local::cache::s3::S3Cache;
+use crate::app::wasm::local::cache::{self, DynModuleCache};
+use crate::app::wasm::local::kv::MemoryLimits;
+use crate::app::wasm::local::signature::SignatureVerifier;
+use crate::app::wasm::local::{self, kv, Config as LocalConfig, LocalRunner, Pooling};
+use crate::app::wasm::{ExecutionLimits, Runner};
 use anyhow::{anyhow, Context};
 use clap::Parser;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use workflow_model::model::S3ArtifactRepositoryConfig;
 
 pub trait DependencyProvider {
     fn get_config(&self) -> &Config;
-    fn get_runner(&self) -> Box<dyn Runner + Send + Sync>;
+    fn get_runner(&self) -> Arc<dyn Runner + Send + Sync>;
     fn get_artifact_repository_config(&self) -> Option<S3ArtifactRepositoryConfig>;
+    /// Returns the cache of precompiled modules, which only exists in local mode
+    fn get_module_cache(&self) -> Option<DynModuleCache>;
+    fn get_metrics(&self) -> Arc<Metrics>;
+    /// Returns the OCI images to prefetch at startup
+    fn get_prefetch_modules(&self) -> &[String];
+    /// Returns whether the plugin finished prefetching and is ready to run modules
+    fn is_ready(&self) -> bool;
+    fn set_ready(&self);
 }
 
 pub type DynDependencyProvider = Arc<dyn DependencyProvider + Send + Sync>;
 
 struct RuntimeDependencyProvider {
     config: Config,
-    client: Option<kube::Client>,
     artifact_repository_config: Option<S3ArtifactRepositoryConfig>,
+    runner: Arc<dyn Runner + Send + Sync>,
+    module_cache: Option<DynModuleCache>,
+    metrics: Arc<Metrics>,
+    prefetch_modules: Vec<String>,
+    ready: AtomicBool,
 }
 
 pub async fn initialize() -> anyhow::Result<DynDependencyProvider> {
@@ -52,39 +74,162 @@ pub async fn initialize() -> anyhow::Result<DynDependencyProvider> {
         None => None,
     };
 
+    let mut prefetch_modules = config.prefetch_modules.to_owned();
+    if let Some(name) = &config.prefetch_config_map {
+        let client = client
+            .as_ref()
+            .ok_or_else(|| anyhow!("Kube client is required for the prefetch ConfigMap"))?;
+        let list = k8s::fetch_config_map_key(
+            client,
+            config.plugin_namespace.as_deref(),
+            name,
+            prefetch::CONFIG_MAP_KEY,
+        )
+        .await
+        .context("Fetching modules to prefetch")?;
+        prefetch_modules.extend(prefetch::parse_module_list(&String::from_utf8_lossy(&list)));
+    }
+
+    let metrics = Arc::new(Metrics::default());
+    let (runner, module_cache) =
+        create_runner(&config, &client, &artifact_repository_config, &metrics)?;
+
     let provider = RuntimeDependencyProvider {
         config,
-        client,
         artifact_repository_config,
+        runner,
+        module_cache,
+        metrics,
+        prefetch_modules,
+        ready: AtomicBool::new(false),
     };
     Ok(Arc::new(provider))
 }
 
+/// Creates the runner shared by all requests, and the module cache it uses in local mode
+fn create_runner(
+    config: &Config,
+    client: &Option<kube::Client>,
+    artifact_repository_config: &Option<S3ArtifactRepositoryConfig>,
+    metrics: &Metrics,
+) -> anyhow::Result<(Arc<dyn Runner + Send + Sync>, Option<DynModuleCache>)> {
+    match config.mode() {
+        Mode::Local => {
+            let registry_credentials =
+                load_registry_credentials(config).context("Loading registry credentials")?;
+            let signature_verifier = SignatureVerifier::try_new(
+                config.signature_policies.to_owned(),
+                &config.signature_public_keys,
+            )
+            .context("Loading signature verification keys")?;
+            let pooling = config.pooling_instances.map(|instances| Pooling {
+                instances,
+                memory_reservation: config.pooling_memory_reservation.bytes(),
+            });
+            let engine = local::setup_engine(&pooling).context("Creating Wasm engine")?;
+            let (s3_cache, s3_precompiled_cache) = match &config.s3_cache_prefix {
+                Some(prefix) => {
+                    let repository = artifact_repository_config.to_owned().ok_or_else(|| {
+                        anyhow!("S3 cache requires the artifact repository of the Argo controller")
+                    })?;
+                    let precompiled = match &config.s3_cache_key_file {
+                        Some(path) => {
+                            let key = std::fs::read(path).context(format!("Reading {}", path))?;
+                            Some(
+                                S3Cache::try_new(repository.to_owned(), prefix)
+                                    .and_then(|cache| cache.with_hmac_key(key.trim_ascii()))
+                                    .context("Creating S3 cache of precompiled modules")?,
+                            )
+                        }
+                        None => None,
+                    };
+                    let wasm = S3Cache::try_new(repository, prefix).context("Creating S3 cache")?;
+                    (Some(wasm), precompiled)
+                }
+                None if config.s3_cache_key_file.is_some() => {
+                    return Err(anyhow!("S3 cache key file requires --s3-cache-prefix"));
+                }
+                None => (None, None),
+            };
+            let local_cache =
+                cache::create_module_cache(&config.fs_cache_dir, &metrics.module_cache);
+            let cache = cache::create_precompiled_cache(&local_cache, s3_precompiled_cache);
+            let wasm_cache = cache::create_wasm_cache(&local_cache, s3_cache);
+            let kv = kv::create_kv_store(
+                &config.kv_config_map,
+                client,
+                config.plugin_namespace.as_deref(),
+                MemoryLimits {
+                    max_entries: config.kv_memory_max_entries,
+                    max_size: config.kv_memory_max_size.bytes(),
+                },
+            )
+            .context("Creating KV store")?;
+            let default_limits = ExecutionLimits {
+                fuel: config.wasm_fuel_limit,
+                timeout: config.wasm_timeout.map(Duration::from_secs),
+                memory: config
+                    .wasm_memory_limit
+                    .map(|memory| memory.bytes() as usize),
+                table_elements: config.wasm_table_elements_limit,
+                instances: config.wasm_instances_limit,
+            };
+            let local_config = LocalConfig {
+                insecure_oci_registries: config.insecure_oci_registries.to_owned(),
+                default_limits,
+                registry_credentials,
+                signature_verifier,
+                module_cache_size: config.module_cache_size,
+                log_max_size: config.module_log_limit.bytes() as usize,
+                mount_allowlist: config.mount_allowlist.iter().map(PathBuf::from).collect(),
+            };
+            let runner = LocalRunner::try_new(
+                engine,
+                cache.clone(),
+                wasm_cache,
+                kv,
+                local_config,
+                client.clone(),
+                config.plugin_namespace.to_owned(),
+            )
+            .context("Creating local runner")?;
+            Ok((Arc::new(runner), Some(local_cache)))
+        }
+        Mode::Distributed => {
+            tracing::warn!("Distributed mode is experimental!");
+            let client = client.as_ref().unwrap().clone();
+            let namespace = config.plugin_namespace.to_owned();
+            let wait_config = DistributedConfig {
+                wait_duration: config.distributed_wait_duration,
+            };
+            let runner = DistributedRunner::new(client, namespace, wait_config);
+            Ok((Arc::new(runner), None))
+        }
+    }
+}
+
+/// Loads the credentials from the registry auth file, overridden by credentials given as option
+fn load_registry_credentials(config: &Config) -> anyhow::Result<RegistryCredentials> {
+    let mut credentials = match &config.registry_auth_file {
+        Some(path) => {
+            let json = std::fs::read(path).context(format!("Reading {}", path))?;
+            RegistryCredentials::from_docker_config(&json)?
+        }
+        None => RegistryCredentials::default(),
+    };
+    for entry in &config.registry_credentials {
+        credentials.insert(&entry.registry, entry.credential.to_owned());
+    }
+    Ok(credentials)
+}
+
 impl DependencyProvider for RuntimeDependencyProvider {
     fn get_config(&self) -> &Config {
         &self.config
     }
 
-    fn get_runner(&self) -> Box<dyn Runner + Send + Sync> {
-        match self.config.mode() {
-            Mode::Local => {
-                let insecure_oci_registries: Vec<String> =
-                    self.config.insecure_oci_registries.to_owned();
-                let cache = cache::create_module_cache(&self.config.fs_cache_dir);
-                let runner = LocalRunner::new(cache, insecure_oci_registries);
-                Box::new(runner)
-            }
-            Mode::Distributed => {
-                tracing::warn!("Distributed mode is experimental!");
-                let client = self.client.as_ref().unwrap().clone();
-                let namespace = self.config.plugin_namespace.to_owned();
-                let wait_config = DistributedConfig {
-                    wait_duration: self.config.distributed_wait_duration,
-                };
-                let runner = DistributedRunner::new(client, namespace, wait_config);
-                Box::new(runner)
-            }
-        }
+    fn get_runner(&self) -> Arc<dyn Runner + Send + Sync> {
+        self.runner.clone()
     }
 
     fn get_artifact_repository_config(&self) -> Option<S3ArtifactRepositoryConfig> {
@@ -92,4 +237,24 @@ impl DependencyProvider for RuntimeDependencyProvider {
             .as_ref()
             .map(|cfg| cfg.to_owned())
     }
+
+    fn get_module_cache(&self) -> Option<DynModuleCache> {
+        self.module_cache.clone()
+    }
+
+    fn get_metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    fn get_prefetch_modules(&self) -> &[String] {
+        &self.prefetch_modules
+    }
+
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
 }
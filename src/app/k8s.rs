@@ -1,5 +1,6 @@
 use crate::app::config::Config;
 use crate::app::model::argo::ArtifactRepositoryConfig;
+use crate::app::model::registry::RegistryCredentials;
 use anyhow::{anyhow, Context};
 use k8s_openapi::api::core::v1::{ConfigMap, Secret};
 use kube_client::config::{
@@ -164,3 +165,88 @@ pub async fn fetch_artifact_repository_config(
     };
     Ok(config)
 }
+
+/// Fetches the registry credentials from a Secret of type `kubernetes.io/dockerconfigjson`
+pub async fn fetch_registry_credentials(
+    client: &kube::Client,
+    ns: Option<&str>,
+    secret_name: &str,
+) -> anyhow::Result<RegistryCredentials> {
+    let secrets: Api<Secret> = {
+        let client = client.clone();
+        match ns {
+            Some(ns) => Api::namespaced(client, ns),
+            None => Api::default_namespaced(client),
+        }
+    };
+
+    let secret = secrets.get(secret_name).await.context("getting Secret")?;
+    match secret.data {
+        Some(data) => match data.get(DOCKER_CONFIG_JSON_KEY) {
+            Some(data) => RegistryCredentials::from_docker_config(&data.0),
+            None => Err(anyhow!(format!(
+                "Did not find the key {} in the Secret",
+                DOCKER_CONFIG_JSON_KEY
+            ))),
+        },
+        None => Err(anyhow!("Did not find data in the Secret")),
+    }
+}
+
+/// Fetches the value of a key in a ConfigMap, looking in binaryData before data
+pub async fn fetch_config_map_key(
+    client: &kube::Client,
+    ns: Option<&str>,
+    configmap_name: &str,
+    key: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let config_maps: Api<ConfigMap> = {
+        let client = client.clone();
+        match ns {
+            Some(ns) => Api::namespaced(client, ns),
+            None => Api::default_namespaced(client),
+        }
+    };
+
+    let config_map = config_maps
+        .get(configmap_name)
+        .await
+        .context("getting ConfigMap")?;
+    if let Some(data) = config_map.binary_data.unwrap_or_default().get(key) {
+        return Ok(data.0.to_owned());
+    }
+    match config_map.data.unwrap_or_default().get(key) {
+        Some(data) => Ok(data.as_bytes().to_vec()),
+        None => Err(anyhow!(format!(
+            "Did not find the key {} in the ConfigMap",
+            key
+        ))),
+    }
+}
+
+/// Fetches the value of a key in a Secret
+pub async fn fetch_secret_key(
+    client: &kube::Client,
+    ns: Option<&str>,
+    secret_name: &str,
+    key: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let secrets: Api<Secret> = {
+        let client = client.clone();
+        match ns {
+            Some(ns) => Api::namespaced(client, ns),
+            None => Api::default_namespaced(client),
+        }
+    };
+
+    let secret = secrets.get(secret_name).await.context("getting Secret")?;
+    match secret.data.unwrap_or_default().get(key) {
+        Some(data) => Ok(data.0.to_owned()),
+        None => Err(anyhow!(format!(
+            "Did not find the key {} in the Secret",
+            key
+        ))),
+    }
+}
+
+const DOCKER_CONFIG_JSON_KEY: &str = ".dockerconfigjson";
@@ -0,0 +1,18 @@
+use crate::app::wasm::local::cache::CacheMetrics;
+use std::sync::Arc;
+
+/// Metrics of the plugin, served in the Prometheus text format on `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub module_cache: Arc<CacheMetrics>,
+}
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.module_cache
+            .render(&mut out)
+            .expect("write metrics to string");
+        out
+    }
+}
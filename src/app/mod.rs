@@ -1,7 +1,9 @@
 pub mod config;
 pub mod dependencies;
 pub mod k8s;
+pub mod metrics;
 pub mod model;
+pub mod prefetch;
 pub mod tracing;
 pub mod wasm;
 pub mod web;
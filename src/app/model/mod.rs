@@ -1,8 +1,13 @@
+use crate::app::model::quantity::Quantity;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
 use workflow_model::model::{ArtifactRef, Parameter, Phase};
 
 pub mod argo;
+pub mod quantity;
+pub mod registry;
+pub mod signature;
 
 #[derive(Deserialize, Debug)]
 #[allow(dead_code)]
@@ -48,14 +53,46 @@ pub struct Plugin {
 pub struct WasmPluginConfig {
     pub module: ModuleSource,
     pub permissions: Option<ModulePermissions>,
+    pub limits: Option<ModuleLimits>,
+    pub image_pull_secrets: Option<Vec<ImagePullSecret>>,
+    pub logs: Option<ModuleLogs>,
+    pub env: Option<Vec<EnvVar>>,
+    pub args: Option<Vec<String>>,
+    /// Plugin options of the module, which are all keys that the plugin does not read itself
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+impl WasmPluginConfig {
+    /// Keys that earlier versions of the plugin passed to the module as plugin options
+    pub const FORMER_PLUGIN_OPTIONS: [&'static str; 5] =
+        ["limits", "image_pull_secrets", "logs", "env", "args"];
+
+    /// Returns the keys of the template that are read by the plugin now, although earlier versions
+    /// passed them to the module as plugin options
+    pub fn former_plugin_options(&self) -> Vec<&'static str> {
+        let set = [
+            self.limits.is_some(),
+            self.image_pull_secrets.is_some(),
+            self.logs.is_some(),
+            self.env.is_some(),
+            self.args.is_some(),
+        ];
+        WasmPluginConfig::FORMER_PLUGIN_OPTIONS
+            .into_iter()
+            .zip(set)
+            .filter_map(|(key, set)| set.then_some(key))
+            .collect()
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[allow(dead_code)]
 pub struct ModulePermissions {
     pub http: Option<HTTPPermissions>,
+    pub secrets: Option<Vec<SecretPermission>>,
+    pub filesystem: Option<FilesystemPermissions>,
+    pub kv: Option<KVPermissions>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -65,17 +102,193 @@ pub struct HTTPPermissions {
     // TODO this should be easier to accomplish
     #[serde(default = "default_max_concurrent_requests")]
     pub max_concurrent_requests: u32,
+    /// Request methods the module may use, all methods if absent
+    pub allowed_methods: Option<Vec<String>>,
+    /// URL path prefixes the module may request per host, all paths of hosts without an entry
+    pub allowed_paths: Option<Vec<HTTPPathPermission>>,
+    pub max_request_body_size: Option<Quantity>,
+    pub max_response_body_size: Option<Quantity>,
+    /// Maximum number of requests the module may send in one invocation
+    pub max_requests: Option<u32>,
+    /// Maximum wall-clock time of each request, including reading the response body
+    pub timeout_seconds: Option<u64>,
+    /// Headers the plugin adds to the requests, so the module never sees the credentials
+    pub headers: Option<Vec<HTTPHeaderInjection>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct HTTPPathPermission {
+    pub host: String,
+    pub prefixes: Vec<String>,
+}
+
+/// HTTPHeaderInjection sets the header name of requests to host to the value of a Secret key,
+/// prepended with prefix (e.g. `Bearer `)
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct HTTPHeaderInjection {
+    pub host: String,
+    pub name: String,
+    pub secret_key_ref: KeyRef,
+    pub prefix: Option<String>,
 }
 
 fn default_max_concurrent_requests() -> u32 {
     8
 }
 
+/// SecretPermission exposes the key of a Secret to the module, as read-only file at
+/// `/work/secrets/<name>/<key>` or as the environment variable env
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct SecretPermission {
+    pub name: String,
+    pub key: String,
+    pub env: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct FilesystemPermissions {
+    pub mounts: Vec<MountPermission>,
+}
+
+/// MountPermission preopens a host directory at guest_path. The host path must be inside one of
+/// the paths the plugin allows to be mounted.
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct MountPermission {
+    pub host_path: String,
+    pub guest_path: String,
+    #[serde(default = "default_mount_mode")]
+    pub mode: MountMode,
+}
+
+/// KVPermissions give the module access to the key-value store of its workflow, shared by all
+/// steps of the workflow
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct KVPermissions {
+    /// Also allow access to the global scope, which is shared by all workflows
+    #[serde(default)]
+    pub global: bool,
+    pub max_value_size: Option<Quantity>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountMode {
+    #[serde(rename = "read_only")]
+    ReadOnly,
+    #[serde(rename = "read_write")]
+    ReadWrite,
+}
+
+fn default_mount_mode() -> MountMode {
+    MountMode::ReadOnly
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct ModuleLimits {
+    /// Maximum number of fuel units the module may consume
+    pub fuel: Option<u64>,
+    /// Maximum wall-clock time the module may run for
+    pub timeout_seconds: Option<u64>,
+    /// Maximum size of each linear memory of the module
+    pub memory: Option<Quantity>,
+    /// Maximum number of elements in each table of the module
+    pub table_elements: Option<u32>,
+    /// Maximum number of instances the module may create
+    pub instances: Option<usize>,
+}
+
 #[derive(Deserialize, Debug)]
 #[allow(dead_code)]
+pub struct ModuleLogs {
+    /// Return stdout and stderr as the output parameters `stdout` and `stderr`
+    #[serde(default)]
+    pub parameters: bool,
+    /// Upload stdout and stderr as the output artifact `logs`
+    #[serde(default)]
+    pub artifact: bool,
+    /// Return the HTTP requests of the module as the output parameter `http_calls`
+    #[serde(default)]
+    pub http_calls: bool,
+}
+
+/// EnvVar sets an environment variable of the module to a literal value or a value from a source
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: Option<String>,
+    pub value_from: Option<EnvVarSource>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub enum EnvVarSource {
+    /// Name of an input parameter of the template
+    #[serde(rename = "parameter")]
+    Parameter(String),
+    #[serde(rename = "secret_key_ref")]
+    SecretKeyRef(KeyRef),
+}
+
+/// ImagePullSecret references a Secret of type `kubernetes.io/dockerconfigjson`
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct ImagePullSecret {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
 pub enum ModuleSource {
     #[serde(rename = "oci")]
     OCI(String),
+    #[serde(rename = "http")]
+    HTTP(HTTPModuleSource),
+    #[serde(rename = "config_map")]
+    ConfigMap(KeyRef),
+    #[serde(rename = "secret")]
+    Secret(KeyRef),
+    /// Base64-encoded module, meant for tiny modules in tests
+    #[serde(rename = "inline")]
+    Inline(String),
+}
+
+// Inline modules are too large to be logged
+impl Debug for ModuleSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleSource::OCI(image) => f.debug_tuple("OCI").field(image).finish(),
+            ModuleSource::HTTP(source) => f.debug_tuple("HTTP").field(source).finish(),
+            ModuleSource::ConfigMap(key_ref) => f.debug_tuple("ConfigMap").field(key_ref).finish(),
+            ModuleSource::Secret(key_ref) => f.debug_tuple("Secret").field(key_ref).finish(),
+            ModuleSource::Inline(module) => f
+                .debug_tuple("Inline")
+                .field(&format!("<{} bytes base64>", module.len()))
+                .finish(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct HTTPModuleSource {
+    pub url: String,
+    /// Hex-encoded SHA-256 digest of the module
+    pub sha256: String,
+}
+
+/// KeyRef references a key in a ConfigMap or Secret
+#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+pub struct KeyRef {
+    pub name: String,
+    pub key: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -104,6 +317,9 @@ pub struct ExecuteTemplateResult {
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub outputs: Option<Outputs>,
+    /// Progress of the node as `N/M`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<String>,
 }
 
 impl ExecuteTemplateResult {
@@ -124,6 +340,33 @@ impl ExecuteTemplateResult {
             phase: src.phase,
             message: src.message,
             outputs,
+            progress: src.progress,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::WasmPluginConfig;
+    use serde_json::json;
+
+    #[test]
+    fn test_former_plugin_options() {
+        let config: WasmPluginConfig = serde_json::from_value(json!({
+            "module": { "oci": "ghcr.io/my-org/my-module:v1" },
+            "args": ["convert"],
+            "logs": {},
+            "format": "png",
+        }))
+        .unwrap();
+        assert_eq!(vec!["logs", "args"], config.former_plugin_options());
+        assert_eq!(vec!["format"], config.extra.keys().collect::<Vec<_>>());
+
+        let mut options = json!({ "module": { "oci": "ghcr.io/my-org/my-module:v1" } });
+        for key in WasmPluginConfig::FORMER_PLUGIN_OPTIONS {
+            options[key] = json!(null);
         }
+        let config: WasmPluginConfig = serde_json::from_value(options).unwrap();
+        assert!(config.extra.is_empty());
     }
 }
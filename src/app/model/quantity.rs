@@ -0,0 +1,89 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+/// Quantity is an amount of bytes written like a Kubernetes resource quantity (e.g. `256Mi`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quantity(pub u64);
+
+impl Quantity {
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+const SUFFIXES: [(&str, u64); 8] = [
+    ("Ki", 1 << 10),
+    ("Mi", 1 << 20),
+    ("Gi", 1 << 30),
+    ("Ti", 1 << 40),
+    ("k", 1_000),
+    ("M", 1_000_000),
+    ("G", 1_000_000_000),
+    ("T", 1_000_000_000_000),
+];
+
+impl FromStr for Quantity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, multiplier) = SUFFIXES
+            .iter()
+            .find_map(|(suffix, multiplier)| {
+                s.strip_suffix(suffix).map(|number| (number, *multiplier))
+            })
+            .unwrap_or((s, 1));
+        let number: u64 = number
+            .parse()
+            .map_err(|_| anyhow!(format!("Invalid quantity '{}'", s)))?;
+        let bytes = number
+            .checked_mul(multiplier)
+            .ok_or_else(|| anyhow!(format!("Quantity '{}' is too large", s)))?;
+        Ok(Quantity(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Quantity {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Quantity(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(Quantity(bytes)),
+            Raw::Quantity(s) => Quantity::from_str(&s).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::quantity::Quantity;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(Quantity(1024), Quantity::from_str("1024").unwrap());
+        assert_eq!(
+            Quantity(256 * 1024 * 1024),
+            Quantity::from_str("256Mi").unwrap()
+        );
+        assert_eq!(Quantity(2_000_000_000), Quantity::from_str("2G").unwrap());
+        assert!(Quantity::from_str("256MB").is_err());
+        assert!(Quantity::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let quantity: Quantity = serde_yaml::from_str("64Ki").unwrap();
+        assert_eq!(Quantity(64 * 1024), quantity);
+        let quantity: Quantity = serde_yaml::from_str("4096").unwrap();
+        assert_eq!(Quantity(4096), quantity);
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::str::FromStr;
+
+/// RegistryCredential authenticates the plugin with an OCI registry
+#[derive(Clone, PartialEq)]
+pub enum RegistryCredential {
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// Token that is sent as password of basic auth, since oci-distribution does not support
+    /// bearer tokens. Registries like GHCR accept tokens this way, registries that expect
+    /// a bearer token or an OAuth2 refresh token exchange reject it.
+    PasswordToken(String),
+}
+
+impl Debug for RegistryCredential {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryCredential::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+            RegistryCredential::PasswordToken(_) => {
+                f.debug_tuple("PasswordToken").field(&"<redacted>").finish()
+            }
+        }
+    }
+}
+
+/// RegistryCredentials holds at most one credential per registry host
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegistryCredentials {
+    by_host: HashMap<String, RegistryCredential>,
+}
+
+impl RegistryCredentials {
+    /// Parses a Docker config.json (the format of `kubernetes.io/dockerconfigjson` Secrets, too)
+    pub fn from_docker_config(json: &[u8]) -> anyhow::Result<Self> {
+        let config: DockerConfig = serde_json::from_slice(json).context("Parsing Docker config")?;
+        let mut credentials = RegistryCredentials::default();
+        for (registry, auth) in config.auths {
+            if let Some(credential) = auth
+                .credential()
+                .context(format!("Parsing credentials for registry {}", registry))?
+            {
+                credentials.insert(&registry, credential);
+            }
+        }
+        Ok(credentials)
+    }
+
+    pub fn insert(&mut self, registry: &str, credential: RegistryCredential) {
+        self.by_host.insert(registry_host(registry), credential);
+    }
+
+    /// Adds all credentials of other, replacing any credential for the same host
+    pub fn extend(&mut self, other: RegistryCredentials) {
+        self.by_host.extend(other.by_host);
+    }
+
+    pub fn get(&self, registry: &str) -> Option<&RegistryCredential> {
+        self.by_host.get(&registry_host(registry))
+    }
+}
+
+/// Normalizes a registry as given in a Docker config (e.g. `https://index.docker.io/v1/`) to
+/// the host of an OCI reference (e.g. `docker.io`)
+fn registry_host(registry: &str) -> String {
+    let registry = registry
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let host = registry.split('/').next().unwrap_or(registry);
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io".to_string(),
+        host => host.to_string(),
+    }
+}
+
+#[derive(Deserialize)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+}
+
+#[derive(Deserialize)]
+struct DockerAuth {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    identitytoken: Option<String>,
+    registrytoken: Option<String>,
+}
+
+impl DockerAuth {
+    fn credential(self) -> anyhow::Result<Option<RegistryCredential>> {
+        if let (Some(username), Some(password)) = (self.username, self.password) {
+            return Ok(Some(RegistryCredential::Basic { username, password }));
+        }
+        if let Some(auth) = self.auth {
+            let auth = base64::decode(auth).context("Decoding auth")?;
+            let auth = String::from_utf8(auth).context("Parsing auth as UTF-8")?;
+            return match auth.split_once(':') {
+                Some((username, password)) => Ok(Some(RegistryCredential::Basic {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                })),
+                None => Err(anyhow!(
+                    "Expected auth to be formatted as username:password"
+                )),
+            };
+        }
+        if self.registrytoken.is_some() {
+            return Err(anyhow!(
+                "registrytoken is a bearer token, which is not supported, use a username and password or an identitytoken instead"
+            ));
+        }
+        Ok(self.identitytoken.map(RegistryCredential::PasswordToken))
+    }
+}
+
+/// RegistryCredentialEntry is a credential given on the command line as `host=username:password`
+/// or `host=token`. A token is sent as PasswordToken, so bearer tokens are rejected.
+#[derive(Clone, Debug)]
+pub struct RegistryCredentialEntry {
+    pub registry: String,
+    pub credential: RegistryCredential,
+}
+
+impl FromStr for RegistryCredentialEntry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (registry, secret) = s.split_once('=').ok_or_else(|| {
+            anyhow!("Expected registry credential as host=username:password or host=token")
+        })?;
+        let credential = match secret.split_once(':') {
+            Some((username, password)) => RegistryCredential::Basic {
+                username: username.to_string(),
+                password: password.to_string(),
+            },
+            None if secret.starts_with("Bearer ") => {
+                return Err(anyhow!(
+                    "Bearer tokens are not supported, expected registry credential as host=username:password or host=token"
+                ))
+            }
+            None => RegistryCredential::PasswordToken(secret.to_string()),
+        };
+        Ok(RegistryCredentialEntry {
+            registry: registry.to_string(),
+            credential,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::registry::{
+        RegistryCredential, RegistryCredentialEntry, RegistryCredentials,
+    };
+    use indoc::indoc;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_from_docker_config() {
+        let json = indoc! {r#"
+        {
+          "auths": {
+            "https://index.docker.io/v1/": { "auth": "dXNlcjpzZWNyZXQ=" },
+            "ghcr.io": { "username": "octocat", "password": "ghp_123" },
+            "myregistry.azurecr.io": { "identitytoken": "eyJhbGciOi" },
+            "quay.io": {}
+          }
+        }
+        "#};
+        let credentials = RegistryCredentials::from_docker_config(json.as_bytes()).unwrap();
+
+        assert_eq!(
+            Some(&RegistryCredential::Basic {
+                username: "user".to_string(),
+                password: "secret".to_string()
+            }),
+            credentials.get("docker.io")
+        );
+        assert_eq!(
+            Some(&RegistryCredential::Basic {
+                username: "octocat".to_string(),
+                password: "ghp_123".to_string()
+            }),
+            credentials.get("ghcr.io")
+        );
+        assert_eq!(
+            Some(&RegistryCredential::PasswordToken("eyJhbGciOi".to_string())),
+            credentials.get("myregistry.azurecr.io")
+        );
+        assert_eq!(None, credentials.get("quay.io"));
+
+        let json = r#"{"auths": {"harbor.example.com": {"registrytoken": "eyJhbGciOi"}}}"#;
+        assert!(RegistryCredentials::from_docker_config(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_entry_from_str() {
+        let entry = RegistryCredentialEntry::from_str("ghcr.io=octocat:pass:word").unwrap();
+        assert_eq!("ghcr.io", entry.registry);
+        assert_eq!(
+            RegistryCredential::Basic {
+                username: "octocat".to_string(),
+                password: "pass:word".to_string()
+            },
+            entry.credential
+        );
+        let entry = RegistryCredentialEntry::from_str("ghcr.io=token").unwrap();
+        assert_eq!(
+            RegistryCredential::PasswordToken("token".to_string()),
+            entry.credential
+        );
+        assert!(RegistryCredentialEntry::from_str("ghcr.io").is_err());
+        assert!(RegistryCredentialEntry::from_str("ghcr.io=Bearer token").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::anyhow;
+use std::str::FromStr;
+
+/// SignatureMode decides what happens to a module without a valid signature
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureMode {
+    /// Reject the module
+    Enforce,
+    /// Log a warning and run the module anyway
+    Warn,
+    /// Do not check signatures
+    Off,
+}
+
+impl FromStr for SignatureMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enforce" => Ok(SignatureMode::Enforce),
+            "warn" => Ok(SignatureMode::Warn),
+            "off" => Ok(SignatureMode::Off),
+            _ => Err(anyhow!(format!("Unknown signature mode '{}'", s))),
+        }
+    }
+}
+
+/// SignaturePolicy applies a SignatureMode to the repositories matching a pattern, given on the
+/// command line as `pattern=mode` (e.g. `ghcr.io/my-org/*=enforce`)
+#[derive(Clone, Debug)]
+pub struct SignaturePolicy {
+    pub pattern: String,
+    pub mode: SignatureMode,
+}
+
+impl SignaturePolicy {
+    /// Checks whether repository (e.g. `ghcr.io/my-org/my-module`) matches the pattern, in
+    /// which `*` stands for any number of characters
+    pub fn matches(&self, repository: &str) -> bool {
+        let mut parts = self.pattern.split('*');
+        let first = parts.next().unwrap_or_default();
+        let mut rest = match repository.strip_prefix(first) {
+            Some(rest) => rest,
+            None => return false,
+        };
+        let parts: Vec<&str> = parts.collect();
+        let (last, middle) = match parts.split_last() {
+            Some(split) => split,
+            // Pattern without wildcard
+            None => return rest.is_empty(),
+        };
+        for part in middle {
+            match rest.find(part) {
+                Some(i) => rest = &rest[i + part.len()..],
+                None => return false,
+            }
+        }
+        rest.ends_with(last)
+    }
+}
+
+impl FromStr for SignaturePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, mode) = s
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("Expected signature policy as pattern=mode"))?;
+        Ok(SignaturePolicy {
+            pattern: pattern.to_string(),
+            mode: SignatureMode::from_str(mode)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::signature::{SignatureMode, SignaturePolicy};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_matches() {
+        let policy = SignaturePolicy::from_str("ghcr.io/my-org/*=enforce").unwrap();
+        assert_eq!(SignatureMode::Enforce, policy.mode);
+        assert!(policy.matches("ghcr.io/my-org/my-module"));
+        assert!(!policy.matches("ghcr.io/other-org/my-module"));
+
+        let policy = SignaturePolicy::from_str("*.example.com/*-signed=warn").unwrap();
+        assert!(policy.matches("registry.example.com/team/module-signed"));
+        assert!(!policy.matches("registry.example.com/team/module"));
+
+        let policy = SignaturePolicy::from_str("ghcr.io/my-org/module=off").unwrap();
+        assert!(policy.matches("ghcr.io/my-org/module"));
+        assert!(!policy.matches("ghcr.io/my-org/module-2"));
+
+        assert!(SignaturePolicy::from_str("*=strict").is_err());
+    }
+}
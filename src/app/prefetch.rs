@@ -0,0 +1,55 @@
+use crate::app::wasm::Runner;
+use std::sync::Arc;
+
+/// Key of the ConfigMap that lists the modules to prefetch
+pub const CONFIG_MAP_KEY: &str = "modules";
+
+/// Parses a list of OCI images with one image per line. Empty lines and lines starting with `#`
+/// are skipped.
+pub fn parse_module_list(s: &str) -> Vec<String> {
+    s.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_owned())
+        .collect()
+}
+
+/// Prefetches the modules one after another, so the first steps that run them start without
+/// pulling and compiling them. Modules that fail to prefetch are pulled when they first run.
+pub async fn prefetch_modules(runner: Arc<dyn Runner + Send + Sync>, images: &[String]) {
+    let mut failed = 0;
+    for image in images {
+        if let Err(err) = runner.prefetch(image).await {
+            tracing::warn!(?err, %image, "Prefetching module failed");
+            failed += 1;
+        }
+    }
+    tracing::info!(
+        "Prefetched {} of {} modules",
+        images.len() - failed,
+        images.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::prefetch::parse_module_list;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_module_list() {
+        let list = indoc! {"
+            # Modules of the nightly workflows
+            ghcr.io/shark/wasm-workflows-plugin-ferris-says:latest
+
+              ghcr.io/shark/wasm-workflows-plugin-text2qr@sha256:0
+        "};
+        assert_eq!(
+            vec![
+                "ghcr.io/shark/wasm-workflows-plugin-ferris-says:latest",
+                "ghcr.io/shark/wasm-workflows-plugin-text2qr@sha256:0"
+            ],
+            parse_module_list(list)
+        );
+    }
+}
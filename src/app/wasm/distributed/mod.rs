@@ -1,11 +1,13 @@
-use crate::app::model::ModulePermissions;
-use crate::app::wasm::{Runner, WasmError};
-use anyhow::anyhow;
+use crate::app::model::{EnvVarSource, ModuleSource, WasmPluginConfig};
+use crate::app::wasm::{self, Runner, WasmError};
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
 use futures::stream::{BoxStream, SelectAll};
 use futures::TryStreamExt;
 use futures::{stream, try_join, StreamExt};
-use k8s_openapi::api::core::v1::{ConfigMap, Container, Pod, PodSpec, Toleration};
+use k8s_openapi::api::core::v1::{
+    ConfigMap, Container, EnvVar, Pod, PodSpec, SecretKeySelector, Toleration,
+};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
 use kube::api::PostParams;
 use kube::{Api, ResourceExt};
@@ -43,15 +45,35 @@ impl Runner for DistributedRunner {
     )]
     async fn run(
         &self,
-        oci_image: &str,
         invocation: PluginInvocation,
-        _perms: &Option<ModulePermissions>,
+        config: &WasmPluginConfig,
         artifact_repo_config: Option<S3ArtifactRepositoryConfig>,
     ) -> anyhow::Result<PluginResult, WasmError> {
+        // Module pods are started from the OCI image, other sources cannot be run distributed
+        let oci_image = match &config.module {
+            ModuleSource::OCI(image) => image,
+            _ => {
+                return Err(WasmError::Retrieve(anyhow!(
+                    "Distributed mode only supports OCI modules"
+                )))
+            }
+        };
+        if let Some(perms) = &config.permissions {
+            if perms.secrets.is_some() || perms.filesystem.is_some() || perms.kv.is_some() {
+                return Err(WasmError::EnvironmentSetup(anyhow!(
+                    "Distributed mode does not support secret, filesystem and kv permissions"
+                )));
+            }
+        }
+        let env = container_env(config, &invocation).map_err(|err| {
+            WasmError::EnvironmentSetup(err.context("Resolving module environment failed"))
+        })?;
         let config_map_name = self
             .create_config_map(&invocation, &artifact_repo_config, &Span::current())
             .await?;
-        let pod_name = self.create_pod(&config_map_name, oci_image).await?;
+        let pod_name = self
+            .create_pod(&config_map_name, oci_image, env, config.args.to_owned())
+            .await?;
         let result = self.wait_for_result(&config_map_name, &pod_name).await;
         try_join!(
             self.delete_pod(&pod_name),
@@ -125,6 +147,8 @@ impl DistributedRunner {
         &self,
         config_map_name: &str,
         oci_image: &str,
+        env: Vec<EnvVar>,
+        args: Option<Vec<String>>,
     ) -> anyhow::Result<String, WasmError> {
         let pods: Api<Pod> = self.api();
         let pod = Pod {
@@ -137,6 +161,8 @@ impl DistributedRunner {
                 containers: vec![Container {
                     name: config_map_name.to_owned(),
                     image: Some(oci_image.to_owned()),
+                    env: Some(env),
+                    args,
                     ..Default::default()
                 }],
                 node_selector: Some(BTreeMap::from([(
@@ -317,5 +343,50 @@ pub struct Config {
     pub wait_duration: u16,
 }
 
+/// Returns the environment variables of the template for the module container. Secrets are
+/// referenced, so that their values do not end up in the Pod spec.
+fn container_env(
+    config: &WasmPluginConfig,
+    invocation: &PluginInvocation,
+) -> anyhow::Result<Vec<EnvVar>> {
+    config
+        .env
+        .iter()
+        .flatten()
+        .map(|var| {
+            let (value, value_from) = match (&var.value, &var.value_from) {
+                (Some(value), _) => (Some(value.to_owned()), None),
+                (None, Some(EnvVarSource::Parameter(name))) => {
+                    let value = wasm::parameter_value(invocation, name)
+                        .context(format!("Resolving environment variable {}", var.name))?;
+                    (Some(value), None)
+                }
+                (None, Some(EnvVarSource::SecretKeyRef(key_ref))) => {
+                    let value_from = k8s_openapi::api::core::v1::EnvVarSource {
+                        secret_key_ref: Some(SecretKeySelector {
+                            name: Some(key_ref.name.to_owned()),
+                            key: key_ref.key.to_owned(),
+                            optional: None,
+                        }),
+                        ..Default::default()
+                    };
+                    (None, Some(value_from))
+                }
+                (None, None) => {
+                    return Err(anyhow!(format!(
+                        "Environment variable {} needs value or value_from",
+                        var.name
+                    )))
+                }
+            };
+            Ok(EnvVar {
+                name: var.name.to_owned(),
+                value,
+                value_from,
+            })
+        })
+        .collect()
+}
+
 const DEFAULT_NAMESPACE: &str = "default";
 const NAME_PREFIX: &str = "wasm-workflow-";
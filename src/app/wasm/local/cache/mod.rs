@@ -0,0 +1,641 @@
+//! Cache of modules, layered as the linked modules that LocalRunner keeps in memory, the files of
+//! the FSCache and the objects of the S3Cache
+
+use crate::app::wasm::local::cache::s3::S3Cache;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::fs;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+
+pub mod s3;
+
+#[async_trait]
+pub trait ModuleCache {
+    async fn get(&self, image: &str) -> Result<Option<Vec<u8>>>;
+    async fn put(&self, image: &str, data: &[u8]) -> Result<()>;
+    /// Evicts the entries that were not accessed within the max age of the policy, then the least
+    /// recently accessed entries until the cache fits into its max size
+    fn purge(&self, policy: &PurgePolicy) -> Result<()>;
+}
+
+pub type DynModuleCache = Arc<dyn ModuleCache + Send + Sync>;
+
+pub(crate) fn create_module_cache(
+    fs_cache_dir: &Option<String>,
+    metrics: &Arc<CacheMetrics>,
+) -> DynModuleCache {
+    match fs_cache_dir {
+        Some(dir) => Arc::new(new_fs_cache(PathBuf::from(dir), metrics.clone())),
+        None => Arc::new(new_nop_cache()),
+    }
+}
+
+/// Creates the cache of the Wasm bytes of modules, which may be shared with the other plugins
+/// through S3. Wasm bytes are verified against the source of the module before they are compiled.
+pub(crate) fn create_wasm_cache(local: &DynModuleCache, s3: Option<S3Cache>) -> DynModuleCache {
+    let local = local.clone();
+    match s3 {
+        Some(remote) => Arc::new(LayeredCache {
+            local,
+            remote: Arc::new(remote),
+        }),
+        None => local,
+    }
+}
+
+/// Creates the cache of precompiled modules, which is only shared with the other plugins through
+/// S3 if the objects are authenticated, since wasmtime does not validate precompiled modules when
+/// they are deserialized
+pub(crate) fn create_precompiled_cache(
+    local: &DynModuleCache,
+    s3: Option<S3Cache>,
+) -> DynModuleCache {
+    match s3 {
+        Some(remote) if remote.is_authenticated() => create_wasm_cache(local, Some(remote)),
+        _ => local.clone(),
+    }
+}
+
+/// LayeredCache looks up modules in the cache of the plugin first, then in the cache shared by
+/// all plugins, and copies the modules it finds there to the local cache
+pub struct LayeredCache {
+    local: DynModuleCache,
+    remote: DynModuleCache,
+}
+
+#[async_trait]
+impl ModuleCache for LayeredCache {
+    async fn get(&self, image: &str) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.local.get(image).await? {
+            return Ok(Some(data));
+        }
+        // The remote cache only saves pulls, so its failures do not fail the module
+        let data = match self.remote.get(image).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                tracing::warn!(?err, "Getting {} from remote module cache failed", image);
+                return Ok(None);
+            }
+        };
+        self.local.put(image, &data).await?;
+        Ok(Some(data))
+    }
+
+    async fn put(&self, image: &str, data: &[u8]) -> Result<()> {
+        self.local.put(image, data).await?;
+        if let Err(err) = self.remote.put(image, data).await {
+            tracing::warn!(?err, "Putting {} into remote module cache failed", image);
+        }
+        Ok(())
+    }
+
+    /// Purges the local cache. The remote cache is shared, so its objects are expired by the
+    /// lifecycle rules of the bucket.
+    fn purge(&self, policy: &PurgePolicy) -> Result<()> {
+        self.local.purge(policy)
+    }
+}
+
+/// PurgePolicy limits the size of the cache and the time its entries are kept without being
+/// accessed
+#[derive(Clone, Debug, Default)]
+pub struct PurgePolicy {
+    /// Maximum size of all entries in bytes
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl PurgePolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_size.is_none() && self.max_age.is_none()
+    }
+}
+
+/// Purges the cache every interval for as long as the plugin runs. Purging blocks on the file
+/// system, so it runs on the blocking thread pool.
+pub fn spawn_purge(
+    cache: DynModuleCache,
+    policy: PurgePolicy,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let cache = cache.clone();
+            let policy = policy.clone();
+            match tokio::task::spawn_blocking(move || cache.purge(&policy)).await {
+                Ok(Ok(_)) => (),
+                Ok(Err(err)) => tracing::warn!(?err, "Purging module cache failed"),
+                Err(err) => tracing::warn!(?err, "Purging module cache panicked"),
+            }
+        }
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// The entry was not accessed within the max age
+    Age,
+    /// The entry was the least recently accessed one while the cache exceeded its max size
+    Size,
+    /// The checksum of the entry did not match its contents
+    Corrupted,
+}
+
+impl EvictionReason {
+    fn as_str(&self) -> &str {
+        match self {
+            EvictionReason::Age => "age",
+            EvictionReason::Size => "size",
+            EvictionReason::Corrupted => "corrupted",
+        }
+    }
+}
+
+/// CacheMetrics counts the evictions of the FSCache. The size and number of entries are the ones
+/// after the last purge.
+#[derive(Debug, Default)]
+pub struct CacheMetrics {
+    evictions_age: AtomicU64,
+    evictions_size: AtomicU64,
+    evictions_corrupted: AtomicU64,
+    evicted_bytes: AtomicU64,
+    purge_errors: AtomicU64,
+    size_bytes: AtomicU64,
+    entries: AtomicU64,
+}
+
+impl CacheMetrics {
+    fn record_eviction(&self, reason: EvictionReason, bytes: u64) {
+        let evictions = match reason {
+            EvictionReason::Age => &self.evictions_age,
+            EvictionReason::Size => &self.evictions_size,
+            EvictionReason::Corrupted => &self.evictions_corrupted,
+        };
+        evictions.fetch_add(1, Ordering::Relaxed);
+        self.evicted_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn record_usage(&self, size_bytes: u64, entries: u64) {
+        self.size_bytes.store(size_bytes, Ordering::Relaxed);
+        self.entries.store(entries, Ordering::Relaxed);
+    }
+
+    pub fn evictions(&self, reason: EvictionReason) -> u64 {
+        match reason {
+            EvictionReason::Age => self.evictions_age.load(Ordering::Relaxed),
+            EvictionReason::Size => self.evictions_size.load(Ordering::Relaxed),
+            EvictionReason::Corrupted => self.evictions_corrupted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Writes the metrics in the Prometheus text format
+    pub fn render(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "# HELP wasm_module_cache_evictions_total Entries evicted from the module cache"
+        )?;
+        writeln!(out, "# TYPE wasm_module_cache_evictions_total counter")?;
+        for reason in [
+            EvictionReason::Age,
+            EvictionReason::Size,
+            EvictionReason::Corrupted,
+        ] {
+            writeln!(
+                out,
+                "wasm_module_cache_evictions_total{{reason=\"{}\"}} {}",
+                reason.as_str(),
+                self.evictions(reason)
+            )?;
+        }
+        let metrics = [
+            (
+                "wasm_module_cache_evicted_bytes_total",
+                "counter",
+                "Bytes evicted from the module cache",
+                &self.evicted_bytes,
+            ),
+            (
+                "wasm_module_cache_purge_errors_total",
+                "counter",
+                "Purges of the module cache that failed",
+                &self.purge_errors,
+            ),
+            (
+                "wasm_module_cache_size_bytes",
+                "gauge",
+                "Size of the module cache after the last purge",
+                &self.size_bytes,
+            ),
+            (
+                "wasm_module_cache_entries",
+                "gauge",
+                "Entries of the module cache after the last purge",
+                &self.entries,
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            writeln!(out, "# HELP {} {}", name, help)?;
+            writeln!(out, "# TYPE {} {}", name, kind)?;
+            writeln!(out, "{} {}", name, value.load(Ordering::Relaxed))?;
+        }
+        Ok(())
+    }
+}
+
+/// Length of the SHA-256 checksum of the module that precedes the compressed module in a file
+const CHECKSUM_LEN: usize = 32;
+
+/// Prefix of the temporary files that modules are written to before they are renamed
+const TEMP_FILE_PREFIX: &str = ".tmp";
+
+pub fn new_fs_cache(base_dir: PathBuf, metrics: Arc<CacheMetrics>) -> FSCache {
+    FSCache { base_dir, metrics }
+}
+
+#[derive(Clone, Debug)]
+pub struct FSCache {
+    base_dir: PathBuf,
+    metrics: Arc<CacheMetrics>,
+}
+
+impl FSCache {
+    fn canonical_name(image: &str) -> String {
+        image
+            .chars()
+            .map(|c| match c {
+                '/' => '-',
+                ':' => '-',
+                _ => c,
+            })
+            .collect()
+    }
+
+    /// Returns the cached files. The mtime of a file is the time it was last accessed, since get
+    /// updates it on every hit.
+    fn cached_files(&self) -> Result<Vec<CachedFile>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.base_dir)? {
+            let entry = entry?;
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                // The file was evicted or replaced concurrently
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(
+                        anyhow!(err).context(format!("Reading file \"{:?}\" failed", entry.path()))
+                    )
+                }
+            };
+            // Modules that are being written are not part of the cache yet
+            let is_temp_file = entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(TEMP_FILE_PREFIX);
+            if !metadata.is_file() || is_temp_file {
+                continue;
+            }
+            let accessed_at = metadata.modified().map_err(|err| {
+                anyhow!(err).context(format!(
+                    "Reading mtime of file \"{:?}\" failed",
+                    entry.path()
+                ))
+            })?;
+            files.push(CachedFile {
+                path: entry.path(),
+                size: metadata.len(),
+                accessed_at,
+            });
+        }
+        Ok(files)
+    }
+
+    fn try_purge(&self, policy: &PurgePolicy) -> Result<()> {
+        let files = self.cached_files()?;
+        let total_size: u64 = files.iter().map(|f| f.size).sum();
+        let total_entries = files.len() as u64;
+        let evictions = select_evictions(files, policy, SystemTime::now());
+
+        let mut evicted_size: u64 = 0;
+        for (file, reason) in &evictions {
+            match fs::remove_file(&file.path) {
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => {
+                    return Err(
+                        anyhow!(err).context(format!("Deleting file \"{:?}\" failed", &file.path))
+                    )
+                }
+            }
+            tracing::trace!(path = ?file.path, reason = reason.as_str(), "Evicted cached file");
+            self.metrics.record_eviction(*reason, file.size);
+            evicted_size += file.size;
+        }
+        let size = total_size - evicted_size;
+        self.metrics
+            .record_usage(size, total_entries - evictions.len() as u64);
+
+        if evictions.is_empty() {
+            tracing::trace!(
+                "Not purging cache, total cached files: {} bytes, policy: {:?}",
+                total_size,
+                policy
+            );
+        } else {
+            tracing::info!(
+                "Cache purged, deleted {} bytes in {} files, now using {} bytes",
+                evicted_size,
+                evictions.len(),
+                size
+            );
+        }
+        Ok(())
+    }
+
+    /// Evicts a file whose checksum does not match, so that the module is pulled again
+    fn evict_corrupted(&self, path: &Path, size: u64, err: anyhow::Error) -> Result<()> {
+        tracing::warn!(?err, "Evicting corrupted cached file {:?}", path);
+        match fs::remove_file(path) {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(anyhow!(err).context(format!("Deleting file \"{:?}\" failed", path)))
+            }
+        }
+        self.metrics
+            .record_eviction(EvictionReason::Corrupted, size);
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        let mut f = match File::open(path) {
+            Ok(f) => f,
+            Err(err) => {
+                if err.kind() == ErrorKind::NotFound {
+                    tracing::trace!("Cache miss for {:?}", path);
+                    return Ok(None);
+                }
+                return Err(err.into());
+            }
+        };
+        tracing::trace!("Cache hit for {:?}", path);
+        // The mtime tracks the last access, which purge evicts the least recently used files by
+        if let Err(err) = f.set_modified(SystemTime::now()) {
+            tracing::warn!(?err, "Updating access time of {:?} failed", path);
+        }
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf)?;
+        match decode(&buf) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) => {
+                self.evict_corrupted(path, buf.len() as u64, err)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Writes the module to a temporary file that is renamed to its final name, so that readers
+    /// never see a partially written file and concurrent writers of a module do not interleave
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut f = tempfile::Builder::new()
+            .prefix(TEMP_FILE_PREFIX)
+            .tempfile_in(&self.base_dir)?;
+        f.write_all(&encode(data)?)?;
+        f.as_file().sync_all()?;
+        f.persist(path)
+            .map_err(|err| anyhow!(err.error).context(format!("Renaming to {:?} failed", path)))?;
+        Ok(())
+    }
+}
+
+/// Returns the module of a cached file, which is the SHA-256 checksum of the module followed by
+/// the zstd-compressed module
+fn decode(buf: &[u8]) -> Result<Vec<u8>> {
+    if buf.len() < CHECKSUM_LEN {
+        return Err(anyhow!("File is shorter than its checksum"));
+    }
+    let (checksum, compressed) = buf.split_at(CHECKSUM_LEN);
+    let data = zstd::stream::decode_all(compressed)?;
+    if Sha256::digest(&data).as_slice() != checksum {
+        return Err(anyhow!("Checksum does not match"));
+    }
+    Ok(data)
+}
+
+fn encode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Sha256::digest(data).to_vec();
+    zstd::stream::copy_encode(data, &mut buf, 0)?;
+    Ok(buf)
+}
+
+#[async_trait]
+impl ModuleCache for FSCache {
+    /// Reads the module on the blocking thread pool, since decoding it blocks on the file system
+    /// and zstd
+    #[tracing::instrument(name = "fscache.get")]
+    async fn get(&self, image: &str) -> Result<Option<Vec<u8>>> {
+        let cache = self.clone();
+        let path = self.base_dir.join(FSCache::canonical_name(image));
+        tokio::task::spawn_blocking(move || cache.read(&path)).await?
+    }
+
+    /// Writes the module on the blocking thread pool, since encoding and syncing it blocks on the
+    /// file system and zstd
+    #[tracing::instrument(name = "fscache.put")]
+    async fn put(&self, image: &str, data: &[u8]) -> Result<()> {
+        let cache = self.clone();
+        let path = self.base_dir.join(FSCache::canonical_name(image));
+        let data = data.to_vec();
+        tokio::task::spawn_blocking(move || cache.write(&path, &data)).await?
+    }
+
+    #[tracing::instrument(name = "fscache.purge")]
+    fn purge(&self, policy: &PurgePolicy) -> Result<()> {
+        self.try_purge(policy).inspect_err(|_| {
+            self.metrics.purge_errors.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    path: PathBuf,
+    size: u64,
+    accessed_at: SystemTime,
+}
+
+/// Selects the files that were not accessed within max_age, then the least recently accessed
+/// files until the remaining ones fit into max_size
+fn select_evictions(
+    files: Vec<CachedFile>,
+    policy: &PurgePolicy,
+    now: SystemTime,
+) -> Vec<(CachedFile, EvictionReason)> {
+    let mut evictions = Vec::new();
+    let mut size: u64 = files.iter().map(|f| f.size).sum();
+    for file in files.into_iter().sorted_by_key(|f| f.accessed_at) {
+        let age = now.duration_since(file.accessed_at).unwrap_or_default();
+        let reason = match (policy.max_age, policy.max_size) {
+            (Some(max_age), _) if age > max_age => EvictionReason::Age,
+            (_, Some(max_size)) if size > max_size => EvictionReason::Size,
+            _ => continue,
+        };
+        size -= file.size;
+        evictions.push((file, reason));
+    }
+    evictions
+}
+
+pub fn new_nop_cache() -> NopCache {
+    NopCache {}
+}
+
+pub struct NopCache {}
+
+#[async_trait]
+impl ModuleCache for NopCache {
+    async fn get(&self, _image: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    async fn put(&self, _image: &str, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn purge(&self, _policy: &PurgePolicy) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::cache::{
+        new_fs_cache, CacheMetrics, EvictionReason, LayeredCache, ModuleCache, PurgePolicy,
+    };
+    use std::fs::File;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test]
+    async fn test_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = Arc::new(CacheMetrics::default());
+        let cache = new_fs_cache(dir.path().to_path_buf(), metrics.clone());
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        for (i, image) in ["ghcr.io/a:1", "ghcr.io/b:1", "ghcr.io/c:1"]
+            .iter()
+            .enumerate()
+        {
+            cache.put(image, &[i as u8; 64]).await.unwrap();
+            let path = dir.path().join(image.replace(['/', ':'], "-"));
+            File::open(path)
+                .unwrap()
+                .set_modified(now - hour * (3 - i as u32))
+                .unwrap();
+        }
+        // Accessing a makes b the least recently used entry
+        assert!(cache.get("ghcr.io/a:1").await.unwrap().is_some());
+
+        let size: u64 = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum();
+        let policy = PurgePolicy {
+            max_size: Some(size - 1),
+            max_age: None,
+        };
+        cache.purge(&policy).unwrap();
+        assert!(cache.get("ghcr.io/a:1").await.unwrap().is_some());
+        assert!(cache.get("ghcr.io/b:1").await.unwrap().is_none());
+        assert!(cache.get("ghcr.io/c:1").await.unwrap().is_some());
+        assert_eq!(1, metrics.evictions(EvictionReason::Size));
+
+        let policy = PurgePolicy {
+            max_size: None,
+            max_age: Some(Duration::from_secs(1)),
+        };
+        File::open(dir.path().join("ghcr.io-c-1"))
+            .unwrap()
+            .set_modified(now - hour)
+            .unwrap();
+        cache.purge(&policy).unwrap();
+        assert!(cache.get("ghcr.io/a:1").await.unwrap().is_some());
+        assert!(cache.get("ghcr.io/c:1").await.unwrap().is_none());
+        assert_eq!(1, metrics.evictions(EvictionReason::Age));
+
+        let mut out = String::new();
+        metrics.render(&mut out).unwrap();
+        assert!(out.contains("wasm_module_cache_evictions_total{reason=\"size\"} 1\n"));
+        assert!(out.contains("wasm_module_cache_entries 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_corrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = Arc::new(CacheMetrics::default());
+        let cache = new_fs_cache(dir.path().to_path_buf(), metrics.clone());
+        cache.put("ghcr.io/a:1", b"module").await.unwrap();
+        assert_eq!(
+            Some(b"module".to_vec()),
+            cache.get("ghcr.io/a:1").await.unwrap()
+        );
+
+        let path = dir.path().join("ghcr.io-a-1");
+        let mut buf = std::fs::read(&path).unwrap();
+        buf[0] ^= 0xff;
+        std::fs::write(&path, buf).unwrap();
+        assert_eq!(None, cache.get("ghcr.io/a:1").await.unwrap());
+        assert!(!path.exists());
+        assert_eq!(1, metrics.evictions(EvictionReason::Corrupted));
+
+        // Files that are still being written are not evicted
+        std::fs::write(dir.path().join(".tmp123"), b"partial").unwrap();
+        let policy = PurgePolicy {
+            max_size: Some(0),
+            max_age: None,
+        };
+        cache.purge(&policy).unwrap();
+        assert!(dir.path().join(".tmp123").exists());
+    }
+
+    #[tokio::test]
+    async fn test_layered() {
+        let (local_dir, remote_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let metrics = Arc::new(CacheMetrics::default());
+        let local = Arc::new(new_fs_cache(
+            local_dir.path().to_path_buf(),
+            metrics.clone(),
+        ));
+        let remote = Arc::new(new_fs_cache(remote_dir.path().to_path_buf(), metrics));
+        let cache = LayeredCache {
+            local: local.clone(),
+            remote: remote.clone(),
+        };
+
+        cache.put("ghcr.io/a:1", b"a").await.unwrap();
+        assert_eq!(
+            Some(b"a".to_vec()),
+            remote.get("ghcr.io/a:1").await.unwrap()
+        );
+
+        // Modules compiled by another plugin are copied to the local cache
+        remote.put("ghcr.io/b:1", b"b").await.unwrap();
+        assert_eq!(None, local.get("ghcr.io/b:1").await.unwrap());
+        assert_eq!(Some(b"b".to_vec()), cache.get("ghcr.io/b:1").await.unwrap());
+        assert_eq!(Some(b"b".to_vec()), local.get("ghcr.io/b:1").await.unwrap());
+        assert_eq!(None, cache.get("ghcr.io/c:1").await.unwrap());
+    }
+}
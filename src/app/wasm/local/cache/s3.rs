@@ -0,0 +1,172 @@
+use crate::app::wasm::local::cache::{decode, encode, FSCache, ModuleCache, PurgePolicy};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use workflow_model::host::artifacts::ArtifactManager;
+use workflow_model::model::S3ArtifactRepositoryConfig;
+
+/// Minimum length of the key that authenticates the objects
+const MIN_HMAC_KEY_LEN: usize = 32;
+const HMAC_LEN: usize = 32;
+
+/// S3Cache stores modules in the bucket of the artifact repository, so that the plugins of a
+/// cluster pull and compile each module only once. Objects are stored in the format of the
+/// FSCache. With an HMAC key, the HMAC-SHA256 of an object precedes it, so that the objects can
+/// only be changed by the plugins that know the key.
+pub struct S3Cache {
+    manager: ArtifactManager,
+    prefix: String,
+    hmac_key: Option<PKey<Private>>,
+}
+
+impl S3Cache {
+    pub fn try_new(config: S3ArtifactRepositoryConfig, prefix: &str) -> Result<Self> {
+        Ok(S3Cache {
+            manager: ArtifactManager::try_new(config)?,
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            hmac_key: None,
+        })
+    }
+
+    /// Authenticates the objects with the key, which is required to share precompiled modules,
+    /// since wasmtime does not validate them when they are deserialized
+    pub fn with_hmac_key(mut self, key: &[u8]) -> Result<Self> {
+        if key.len() < MIN_HMAC_KEY_LEN {
+            return Err(anyhow!(format!(
+                "HMAC key must be at least {} bytes long",
+                MIN_HMAC_KEY_LEN
+            )));
+        }
+        self.hmac_key = Some(PKey::hmac(key)?);
+        Ok(self)
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.hmac_key.is_some()
+    }
+
+    fn hmac(key: &PKey<Private>, buf: &[u8]) -> Result<Vec<u8>> {
+        let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+        signer.update(buf)?;
+        Ok(signer.sign_to_vec()?)
+    }
+
+    fn seal(&self, buf: Vec<u8>) -> Result<Vec<u8>> {
+        match &self.hmac_key {
+            Some(key) => {
+                let mut sealed = S3Cache::hmac(key, &buf)?;
+                sealed.extend(buf);
+                Ok(sealed)
+            }
+            None => Ok(buf),
+        }
+    }
+
+    /// Returns the object without its HMAC, if the HMAC matches
+    fn open<'a>(&self, buf: &'a [u8]) -> Result<&'a [u8]> {
+        let key = match &self.hmac_key {
+            Some(key) => key,
+            None => return Ok(buf),
+        };
+        if buf.len() < HMAC_LEN {
+            return Err(anyhow!("Object is shorter than its HMAC"));
+        }
+        let (hmac, object) = buf.split_at(HMAC_LEN);
+        if !openssl::memcmp::eq(hmac, &S3Cache::hmac(key, object)?) {
+            return Err(anyhow!("HMAC does not match"));
+        }
+        Ok(object)
+    }
+
+    fn key(&self, image: &str) -> String {
+        format!("{}/{}", self.prefix, FSCache::canonical_name(image))
+    }
+}
+
+#[async_trait]
+impl ModuleCache for S3Cache {
+    #[tracing::instrument(name = "s3cache.get", skip(self))]
+    async fn get(&self, image: &str) -> Result<Option<Vec<u8>>> {
+        let key = self.key(image);
+        let buf = match self.manager.get_object(&key).await? {
+            Some(buf) => buf,
+            None => return Ok(None),
+        };
+        // A corrupted object is replaced once the module was pulled again
+        match self.open(&buf).and_then(decode) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) => {
+                tracing::warn!(?err, "Ignoring corrupted module cache object {}", key);
+                Ok(None)
+            }
+        }
+    }
+
+    #[tracing::instrument(name = "s3cache.put", skip(self, data))]
+    async fn put(&self, image: &str, data: &[u8]) -> Result<()> {
+        let key = self.key(image);
+        self.manager
+            .put_object(&key, &self.seal(encode(data)?)?)
+            .await
+            .context(format!("Storing module at key {}", key))
+    }
+
+    fn purge(&self, _policy: &PurgePolicy) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::cache::s3::S3Cache;
+    use workflow_model::model::S3ArtifactRepositoryConfig;
+
+    fn config() -> S3ArtifactRepositoryConfig {
+        S3ArtifactRepositoryConfig {
+            bucket: "my-bucket".to_string(),
+            endpoint: "minio:9000".to_string(),
+            insecure: true,
+            access_key: "admin".to_string(),
+            secret_key: "password".to_string(),
+            path_style_endpoint: true,
+            region: "us-east-1".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let cache = S3Cache::try_new(config(), "wasm-modules/").unwrap();
+        assert_eq!(
+            "wasm-modules/ghcr.io-my-org-my-module@sha256-0.wasm",
+            cache.key("ghcr.io/my-org/my-module@sha256:0.wasm")
+        );
+    }
+
+    #[test]
+    fn test_hmac() {
+        let cache = S3Cache::try_new(config(), "wasm-modules")
+            .unwrap()
+            .with_hmac_key(&[1; 32])
+            .unwrap();
+        let sealed = cache.seal(b"module".to_vec()).unwrap();
+        assert_eq!(b"module", cache.open(&sealed).unwrap());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cache.open(&tampered).is_err());
+        assert!(cache.open(b"short").is_err());
+
+        let other = S3Cache::try_new(config(), "wasm-modules")
+            .unwrap()
+            .with_hmac_key(&[2; 32])
+            .unwrap();
+        assert!(other.open(&sealed).is_err());
+
+        assert!(S3Cache::try_new(config(), "wasm-modules")
+            .unwrap()
+            .with_hmac_key(b"short")
+            .is_err());
+    }
+}
@@ -0,0 +1,35 @@
+//! Reads from the linear memory of modules. Pointers and lengths passed to host functions are
+//! controlled by the module, so they are checked against the size of its memory before the host
+//! allocates anything.
+
+use wasmtime::{AsContext, Memory};
+
+/// Copies len bytes at ptr out of the memory, or returns None if they are not within the memory
+pub fn read(memory: &Memory, store: impl AsContext, ptr: u32, len: u32) -> Option<Vec<u8>> {
+    let start = ptr as usize;
+    let end = start.checked_add(len as usize)?;
+    memory
+        .data(store.as_context())
+        .get(start..end)
+        .map(<[u8]>::to_vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::guest_memory::read;
+    use wasmtime::{Engine, Memory, MemoryType, Store};
+
+    #[test]
+    fn test_read() {
+        let engine = Engine::default();
+        let mut store = Store::new(&engine, ());
+        let memory = Memory::new(&mut store, MemoryType::new(1, Some(1))).unwrap();
+        memory.write(&mut store, 100, b"hello").unwrap();
+
+        assert_eq!(Some(b"hello".to_vec()), read(&memory, &store, 100, 5));
+        assert_eq!(Some(Vec::new()), read(&memory, &store, 65536, 0));
+        assert_eq!(None, read(&memory, &store, 65535, 2));
+        assert_eq!(None, read(&memory, &store, 0, u32::MAX));
+        assert_eq!(None, read(&memory, &store, u32::MAX, u32::MAX));
+    }
+}
@@ -0,0 +1,68 @@
+use reqwest::Url;
+use serde::Serialize;
+use tracing::{field, info_span, Span};
+
+/// HttpCall records a request that a module sent or tried to send. The query of the URL is left
+/// out, since it may contain credentials.
+#[derive(Serialize, Debug)]
+pub struct HttpCall {
+    pub method: String,
+    pub host: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HttpCall {
+    pub fn new(method: &str, url: &Url) -> Self {
+        HttpCall {
+            method: method.to_owned(),
+            host: url.host_str().unwrap_or_default().to_owned(),
+            path: url.path().to_owned(),
+            status: None,
+            request_bytes: 0,
+            response_bytes: 0,
+            duration_ms: 0,
+            error: None,
+        }
+    }
+
+    /// Creates the span of the call. Created within the span of the module execution, it becomes
+    /// a child span of it.
+    pub fn span(&self) -> Span {
+        info_span!(
+            "wasm.http_request",
+            http.method = %self.method,
+            http.host = %self.host,
+            http.path = %self.path,
+            http.status_code = field::Empty,
+            http.response_bytes = field::Empty,
+        )
+    }
+
+    /// Records the outcome of the call on its span and emits it as event
+    pub fn trace(&self, span: &Span) {
+        if let Some(status) = self.status {
+            span.record("http.status_code", status);
+        }
+        span.record("http.response_bytes", self.response_bytes);
+        tracing::info!(
+            target: "wasm_http",
+            parent: span,
+            method = %self.method,
+            host = %self.host,
+            path = %self.path,
+            status = ?self.status,
+            request_bytes = self.request_bytes,
+            response_bytes = self.response_bytes,
+            duration_ms = self.duration_ms,
+            error = ?self.error,
+            "HTTP request"
+        );
+    }
+}
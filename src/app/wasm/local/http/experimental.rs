@@ -0,0 +1,410 @@
+//! Compatibility shim for modules built with wasi-experimental-http, like
+//! `wasm-modules/contrib/http-request`. Their requests are sent like wasi:http requests.
+
+use crate::app::wasm::local::guest_memory;
+use crate::app::wasm::local::http::policy::Denied;
+use crate::app::wasm::local::http::{self, HttpCtx, InFlight};
+use bytes::Bytes;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::Method;
+use reqwest::Url;
+use std::str::FromStr;
+use std::time::Duration;
+use wasmtime::{Caller, Linker, Memory};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::types::OutgoingRequestConfig;
+
+/// Module that wasi-experimental-http imports its host functions from
+const MODULE: &str = "wasi_experimental_http";
+
+/// Timeout of each phase of a request, unless the HttpPolicy sets a shorter one. It matches the
+/// default of wasi:http.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub struct HttpResponse {
+    status: u16,
+    headers: HeaderMap,
+    body: Bytes,
+    position: usize,
+    /// Open responses count as requests in flight until the module closes them
+    _in_flight: InFlight,
+}
+
+/// HttpError is returned to the module as the error code of wasi-experimental-http
+#[derive(Debug)]
+enum HttpError {
+    InvalidHandle,
+    MemoryNotFound,
+    MemoryAccessError,
+    BufferTooSmall,
+    HeaderNotFound,
+    Utf8Error,
+    DestinationNotAllowed,
+    InvalidMethod,
+    InvalidEncoding,
+    InvalidUrl,
+    RequestError,
+    RuntimeError,
+    TooManySessions,
+}
+
+impl From<HttpError> for u32 {
+    fn from(err: HttpError) -> Self {
+        match err {
+            HttpError::InvalidHandle => 1,
+            HttpError::MemoryNotFound => 2,
+            HttpError::MemoryAccessError => 3,
+            HttpError::BufferTooSmall => 4,
+            HttpError::HeaderNotFound => 5,
+            HttpError::Utf8Error => 6,
+            HttpError::DestinationNotAllowed => 7,
+            HttpError::InvalidMethod => 8,
+            HttpError::InvalidEncoding => 9,
+            HttpError::InvalidUrl => 10,
+            HttpError::RequestError => 11,
+            HttpError::RuntimeError => 12,
+            HttpError::TooManySessions => 13,
+        }
+    }
+}
+
+fn error_code(result: Result<(), HttpError>) -> u32 {
+    match result {
+        Ok(_) => 0,
+        Err(err) => err.into(),
+    }
+}
+
+/// Defines the host functions of wasi-experimental-http, enforcing the HttpPolicy of the module
+pub fn add_to_linker<T: Send + 'static>(
+    linker: &mut Linker<T>,
+    get: impl Fn(&mut T) -> &mut HttpCtx + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.func_wrap_async(
+        MODULE,
+        "req",
+        move |mut caller: Caller<'_, T>,
+              (
+            url_ptr,
+            url_len,
+            method_ptr,
+            method_len,
+            headers_ptr,
+            headers_len,
+            body_ptr,
+            body_len,
+            status_code_ptr,
+            handle_ptr,
+        ): ReqParams| {
+            Box::new(async move {
+                let request = Request {
+                    url: (url_ptr, url_len),
+                    method: (method_ptr, method_len),
+                    headers: (headers_ptr, headers_len),
+                    body: (body_ptr, body_len),
+                };
+                error_code(send(&mut caller, get, request, status_code_ptr, handle_ptr).await)
+            })
+        },
+    )?;
+    linker.func_wrap(
+        MODULE,
+        "close",
+        move |mut caller: Caller<'_, T>, handle: u32| error_code(close(&mut caller, get, handle)),
+    )?;
+    linker.func_wrap(
+        MODULE,
+        "header_get",
+        move |mut caller: Caller<'_, T>,
+              handle: u32,
+              name_ptr: u32,
+              name_len: u32,
+              value_ptr: u32,
+              value_len: u32,
+              written_ptr: u32| {
+            let buffer = Buffer {
+                ptr: value_ptr,
+                len: value_len,
+                written_ptr,
+            };
+            error_code(header_get(
+                &mut caller,
+                get,
+                handle,
+                (name_ptr, name_len),
+                buffer,
+            ))
+        },
+    )?;
+    linker.func_wrap(
+        MODULE,
+        "headers_get_all",
+        move |mut caller: Caller<'_, T>,
+              handle: u32,
+              buf_ptr: u32,
+              buf_len: u32,
+              written_ptr: u32| {
+            let buffer = Buffer {
+                ptr: buf_ptr,
+                len: buf_len,
+                written_ptr,
+            };
+            error_code(headers_get_all(&mut caller, get, handle, buffer))
+        },
+    )?;
+    linker.func_wrap(
+        MODULE,
+        "body_read",
+        move |mut caller: Caller<'_, T>,
+              handle: u32,
+              buf_ptr: u32,
+              buf_len: u32,
+              written_ptr: u32| {
+            let buffer = Buffer {
+                ptr: buf_ptr,
+                len: buf_len,
+                written_ptr,
+            };
+            error_code(body_read(&mut caller, get, handle, buffer))
+        },
+    )?;
+    Ok(())
+}
+
+type ReqParams = (u32, u32, u32, u32, u32, u32, u32, u32, u32, u32);
+
+/// Buffer of the module that a host function writes to
+struct Buffer {
+    ptr: u32,
+    len: u32,
+    written_ptr: u32,
+}
+
+/// Pointers to and lengths of the parts of a request in the memory of the module
+struct Request {
+    url: (u32, u32),
+    method: (u32, u32),
+    headers: (u32, u32),
+    body: (u32, u32),
+}
+
+async fn send<T: Send>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut HttpCtx,
+    request: Request,
+    status_code_ptr: u32,
+    handle_ptr: u32,
+) -> Result<(), HttpError> {
+    let memory = memory(caller)?;
+    let url = read_string(caller, memory, request.url.0, request.url.1)?;
+    let url = Url::parse(&url).map_err(|_| HttpError::InvalidUrl)?;
+    let method = read_string(caller, memory, request.method.0, request.method.1)?.to_uppercase();
+    let method = Method::from_str(&method).map_err(|_| HttpError::InvalidMethod)?;
+    let headers = read_string(caller, memory, request.headers.0, request.headers.1)?;
+    let headers = string_to_header_map(&headers)?;
+    let body = read_bytes(caller, memory, request.body.0, request.body.1)?;
+    let response = execute(caller, &get, method, url, headers, body).await?;
+
+    let ctx = get(caller.data_mut());
+    let status = response.status;
+    let handle = ctx.next_handle;
+    ctx.next_handle += 1;
+    ctx.responses.insert(handle, response);
+    write(caller, memory, status_code_ptr, &status.to_le_bytes())?;
+    write(caller, memory, handle_ptr, &handle.to_le_bytes())
+}
+
+/// Sends the request if the HttpPolicy allows it and reads the response
+async fn execute<T: Send>(
+    caller: &mut Caller<'_, T>,
+    get: &impl Fn(&mut T) -> &mut HttpCtx,
+    method: Method,
+    url: Url,
+    headers: HeaderMap,
+    body: Vec<u8>,
+) -> Result<HttpResponse, HttpError> {
+    let ctx = get(caller.data_mut());
+    let in_flight = ctx
+        .authorize(method.as_str(), &url, Some(body.len()))
+        .map_err(|denied| match denied {
+            Denied::Host(_) | Denied::Path(_) => HttpError::DestinationNotAllowed,
+            Denied::Method(_) => HttpError::InvalidMethod,
+            Denied::Requests(_) | Denied::ConcurrentRequests(_) => HttpError::TooManySessions,
+            Denied::RequestBodySize(_) => HttpError::RuntimeError,
+        })?;
+    let mut request = hyper::Request::new(http::full(Bytes::from(body)));
+    *request.method_mut() = method;
+    *request.uri_mut() = url.as_str().parse().map_err(|_| HttpError::InvalidUrl)?;
+    *request.headers_mut() = headers;
+    let config = OutgoingRequestConfig {
+        use_tls: url.scheme() == "https",
+        connect_timeout: DEFAULT_TIMEOUT,
+        first_byte_timeout: DEFAULT_TIMEOUT,
+        between_bytes_timeout: DEFAULT_TIMEOUT,
+    };
+
+    let response = ctx
+        .send(url, request, config, in_flight)
+        .await
+        .map_err(|err| {
+            tracing::warn!(?err, "HTTP request failed");
+            match err {
+                ErrorCode::HttpRequestBodySize(_) | ErrorCode::HttpResponseBodySize(_) => {
+                    HttpError::RuntimeError
+                }
+                _ => HttpError::RequestError,
+            }
+        })?;
+    Ok(HttpResponse {
+        status: response.status().as_u16(),
+        headers: response.headers().clone(),
+        body: response.into_body(),
+        position: 0,
+        _in_flight: ctx.track(),
+    })
+}
+
+fn close<T>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut HttpCtx,
+    handle: u32,
+) -> Result<(), HttpError> {
+    get(caller.data_mut())
+        .responses
+        .remove(&handle)
+        .map(|_| ())
+        .ok_or(HttpError::InvalidHandle)
+}
+
+fn header_get<T>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut HttpCtx,
+    handle: u32,
+    name: (u32, u32),
+    buffer: Buffer,
+) -> Result<(), HttpError> {
+    let memory = memory(caller)?;
+    let name = read_string(caller, memory, name.0, name.1)?;
+    let value = response(caller, get, handle)?
+        .headers
+        .get(name.as_str())
+        .ok_or(HttpError::HeaderNotFound)?
+        .as_bytes()
+        .to_vec();
+    write_buffer(caller, memory, &value, buffer)
+}
+
+fn headers_get_all<T>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut HttpCtx,
+    handle: u32,
+    buffer: Buffer,
+) -> Result<(), HttpError> {
+    let memory = memory(caller)?;
+    let headers = header_map_to_string(&response(caller, get, handle)?.headers)?;
+    write_buffer(caller, memory, headers.as_bytes(), buffer)
+}
+
+fn body_read<T>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut HttpCtx,
+    handle: u32,
+    buffer: Buffer,
+) -> Result<(), HttpError> {
+    let memory = memory(caller)?;
+    let response = response(caller, get, handle)?;
+    let end = response
+        .body
+        .len()
+        .min(response.position + buffer.len as usize);
+    let chunk = response.body.slice(response.position..end);
+    response.position = end;
+    write_buffer(caller, memory, &chunk, buffer)
+}
+
+fn response<'a, T>(
+    caller: &'a mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut HttpCtx,
+    handle: u32,
+) -> Result<&'a mut HttpResponse, HttpError> {
+    get(caller.data_mut())
+        .responses
+        .get_mut(&handle)
+        .ok_or(HttpError::InvalidHandle)
+}
+
+fn memory<T>(caller: &mut Caller<'_, T>) -> Result<Memory, HttpError> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or(HttpError::MemoryNotFound)
+}
+
+fn read_bytes<T>(
+    caller: &mut Caller<'_, T>,
+    memory: Memory,
+    ptr: u32,
+    len: u32,
+) -> Result<Vec<u8>, HttpError> {
+    guest_memory::read(&memory, caller, ptr, len).ok_or(HttpError::MemoryAccessError)
+}
+
+fn read_string<T>(
+    caller: &mut Caller<'_, T>,
+    memory: Memory,
+    ptr: u32,
+    len: u32,
+) -> Result<String, HttpError> {
+    String::from_utf8(read_bytes(caller, memory, ptr, len)?).map_err(|_| HttpError::Utf8Error)
+}
+
+fn write<T>(
+    caller: &mut Caller<'_, T>,
+    memory: Memory,
+    ptr: u32,
+    data: &[u8],
+) -> Result<(), HttpError> {
+    memory
+        .write(caller, ptr as usize, data)
+        .map_err(|_| HttpError::MemoryAccessError)
+}
+
+/// Writes data and the number of bytes written to the buffer of the module
+fn write_buffer<T>(
+    caller: &mut Caller<'_, T>,
+    memory: Memory,
+    data: &[u8],
+    buffer: Buffer,
+) -> Result<(), HttpError> {
+    if data.len() > buffer.len as usize {
+        return Err(HttpError::BufferTooSmall);
+    }
+    write(caller, memory, buffer.ptr, data)?;
+    write(
+        caller,
+        memory,
+        buffer.written_ptr,
+        &(data.len() as u32).to_le_bytes(),
+    )
+}
+
+/// Headers are exchanged with the module as lines of `name:value`
+fn string_to_header_map(s: &str) -> Result<HeaderMap, HttpError> {
+    let mut headers = HeaderMap::new();
+    for line in s.lines().filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(HttpError::InvalidEncoding)?;
+        let name = HeaderName::from_str(name).map_err(|_| HttpError::InvalidEncoding)?;
+        let value = HeaderValue::from_str(value).map_err(|_| HttpError::InvalidEncoding)?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+fn header_map_to_string(headers: &HeaderMap) -> Result<String, HttpError> {
+    let mut s = String::new();
+    for (name, value) in headers {
+        let value = value.to_str().map_err(|_| HttpError::InvalidEncoding)?;
+        s.push_str(&format!("{}:{}\n", name, value));
+    }
+    Ok(s)
+}
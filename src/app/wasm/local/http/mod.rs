@@ -0,0 +1,297 @@
+use crate::app::wasm::local::http::audit::HttpCall;
+use crate::app::wasm::local::http::policy::{Denied, HttpPolicy};
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Body;
+use hyper::header::{HeaderName, HeaderValue};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{Instrument, Span};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{
+    default_send_request_handler, HostFutureIncomingResponse, IncomingResponse,
+    OutgoingRequestConfig,
+};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx};
+
+pub mod audit;
+pub mod experimental;
+pub mod policy;
+
+/// HttpCtx sends the requests of one module, made through wasi:http or the wasi-experimental-http
+/// shim, and records the calls it made
+pub struct HttpCtx {
+    policy: HttpPolicy,
+    wasi: WasiHttpCtx,
+    requests: u32,
+    /// Requests of both wasi:http and the shim that were sent and are not done yet
+    in_flight: Arc<AtomicU32>,
+    /// Shared with the requests in flight, which record themselves when they are done
+    calls: Arc<Mutex<Vec<HttpCall>>>,
+    responses: HashMap<u32, experimental::HttpResponse>,
+    next_handle: u32,
+}
+
+impl HttpCtx {
+    pub fn new(policy: HttpPolicy) -> Self {
+        HttpCtx {
+            policy,
+            wasi: WasiHttpCtx::new(),
+            requests: 0,
+            in_flight: Arc::new(AtomicU32::new(0)),
+            calls: Arc::new(Mutex::new(Vec::new())),
+            responses: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    pub fn wasi(&mut self) -> &mut WasiHttpCtx {
+        &mut self.wasi
+    }
+
+    pub fn take_calls(&mut self) -> Vec<HttpCall> {
+        std::mem::take(&mut self.calls.lock().unwrap())
+    }
+
+    /// Checks whether the HttpPolicy allows the request and counts it. The request is in flight
+    /// until the returned InFlight is dropped. Denied requests are recorded right away, since
+    /// they are never sent.
+    pub fn authorize(
+        &mut self,
+        method: &str,
+        url: &Url,
+        body_size: Option<usize>,
+    ) -> Result<InFlight, Denied> {
+        let checked = self
+            .policy
+            .check(method, url, body_size.unwrap_or_default(), self.requests)
+            .and_then(|_| match self.policy.max_concurrent_requests {
+                Some(max) if self.in_flight.load(Ordering::SeqCst) >= max => {
+                    Err(Denied::ConcurrentRequests(max))
+                }
+                _ => Ok(()),
+            });
+        match checked {
+            Ok(_) => {
+                self.requests += 1;
+                Ok(self.track())
+            }
+            Err(denied) => {
+                tracing::warn!("{}", denied);
+                let mut call = HttpCall::new(method, url);
+                call.request_bytes = body_size.unwrap_or_default();
+                call.error = Some(denied.to_string());
+                call.trace(&call.span());
+                self.calls.lock().unwrap().push(call);
+                Err(denied)
+            }
+        }
+    }
+
+    /// Counts a request as in flight until the returned InFlight is dropped
+    fn track(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.in_flight.clone())
+    }
+
+    /// Sends an authorized request and reads the response. The returned future does not borrow
+    /// the HttpCtx, so the module can keep running while it is awaited.
+    pub fn send(
+        &self,
+        url: Url,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+        in_flight: InFlight,
+    ) -> impl Future<Output = Result<hyper::Response<Bytes>, ErrorCode>> + Send + 'static {
+        let policy = self.policy.clone();
+        let calls = self.calls.clone();
+        let mut call = HttpCall::new(request.method().as_str(), &url);
+        let span = call.span();
+        async move {
+            let start = Instant::now();
+            let result = execute(&policy, &url, request, config, &mut call).await;
+            call.status = result
+                .as_ref()
+                .ok()
+                .map(|response| response.status().as_u16());
+            call.response_bytes = result.as_ref().map_or(0, |response| response.body().len());
+            call.duration_ms = start.elapsed().as_millis() as u64;
+            call.error = result.as_ref().err().map(|err| format!("{:?}", err));
+            call.trace(&Span::current());
+            calls.lock().unwrap().push(call);
+            drop(in_flight);
+            result
+        }
+        .instrument(span)
+    }
+}
+
+/// InFlight counts a request towards the max concurrent requests of the HttpPolicy as long as it
+/// exists
+pub struct InFlight(Arc<AtomicU32>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Sends a wasi:http request of a module, enforcing its HttpPolicy
+pub fn send_request(
+    ctx: &mut HttpCtx,
+    request: hyper::Request<HyperOutgoingBody>,
+    config: OutgoingRequestConfig,
+) -> HttpResult<HostFutureIncomingResponse> {
+    let url =
+        Url::parse(&request.uri().to_string()).map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+    // Unless the module set the content length, the size of the body is only known once it has
+    // been written. The limit is enforced on the bytes actually sent either way.
+    let body_size = request.body().size_hint().exact().map(|size| size as usize);
+    let in_flight = ctx
+        .authorize(request.method().as_str(), &url, body_size)
+        .map_err(ErrorCode::from)?;
+    let between_bytes_timeout = config.between_bytes_timeout;
+    let send = ctx.send(url, request, config, in_flight);
+    let handle = wasmtime_wasi::runtime::spawn(async move {
+        Ok(send.await.map(|response| IncomingResponse {
+            resp: response.map(full),
+            worker: None,
+            between_bytes_timeout,
+        }))
+    });
+    Ok(HostFutureIncomingResponse::pending(handle))
+}
+
+impl From<Denied> for ErrorCode {
+    fn from(denied: Denied) -> Self {
+        match denied {
+            Denied::Host(_)
+            | Denied::Path(_)
+            | Denied::Requests(_)
+            | Denied::ConcurrentRequests(_) => ErrorCode::HttpRequestDenied,
+            Denied::Method(_) => ErrorCode::HttpRequestMethodInvalid,
+            Denied::RequestBodySize(size) => ErrorCode::HttpRequestBodySize(Some(size)),
+        }
+    }
+}
+
+/// Sends the request with the injected headers, enforcing the size limits and timeout of the
+/// HttpPolicy. Redirects are not followed, since their target would bypass the HttpPolicy.
+async fn execute(
+    policy: &HttpPolicy,
+    url: &Url,
+    request: hyper::Request<HyperOutgoingBody>,
+    mut config: OutgoingRequestConfig,
+    call: &mut HttpCall,
+) -> Result<hyper::Response<Bytes>, ErrorCode> {
+    let (mut parts, body) = request.into_parts();
+    let body = collect(
+        body,
+        policy.max_request_body_size,
+        ErrorCode::HttpRequestBodySize,
+    )
+    .await?;
+    call.request_bytes = body.len();
+    // Injected headers replace the headers of the module, so it cannot choose the credentials
+    for header in policy.headers(url) {
+        let name = HeaderName::from_str(&header.name).map_err(|_| invalid_header(&header.name))?;
+        let value =
+            HeaderValue::from_str(&header.value).map_err(|_| invalid_header(&header.name))?;
+        parts.headers.insert(name, value);
+    }
+    if let Some(timeout) = policy.timeout {
+        config.connect_timeout = config.connect_timeout.min(timeout);
+        config.first_byte_timeout = config.first_byte_timeout.min(timeout);
+        config.between_bytes_timeout = config.between_bytes_timeout.min(timeout);
+    }
+    let request = hyper::Request::from_parts(parts, full(body));
+
+    let send = async {
+        let response = default_send_request_handler(request, config).await?;
+        // The worker drives the connection, so it is kept until the body is read
+        let _worker = response.worker;
+        let (parts, body) = response.resp.into_parts();
+        let body = collect(
+            body,
+            policy.max_response_body_size,
+            ErrorCode::HttpResponseBodySize,
+        )
+        .await?;
+        Ok(hyper::Response::from_parts(parts, body))
+    };
+    match policy.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, send)
+            .await
+            .map_err(|_| ErrorCode::ConnectionReadTimeout)?,
+        None => send.await,
+    }
+}
+
+/// Reads the whole body, failing with exceeded once it is larger than max_size bytes
+async fn collect(
+    mut body: BoxBody<Bytes, ErrorCode>,
+    max_size: Option<u64>,
+    exceeded: fn(Option<u64>) -> ErrorCode,
+) -> Result<Bytes, ErrorCode> {
+    let mut data: Vec<u8> = Vec::new();
+    while let Some(frame) = body.frame().await {
+        if let Ok(chunk) = frame?.into_data() {
+            data.extend_from_slice(&chunk);
+            if let Some(max_size) = max_size {
+                if data.len() as u64 > max_size {
+                    return Err(exceeded(Some(max_size)));
+                }
+            }
+        }
+    }
+    Ok(Bytes::from(data))
+}
+
+fn full(body: Bytes) -> BoxBody<Bytes, ErrorCode> {
+    Full::new(body).map_err(|never| match never {}).boxed()
+}
+
+fn invalid_header(name: &str) -> ErrorCode {
+    ErrorCode::InternalError(Some(format!("Invalid injected header {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::HTTPPermissions;
+    use crate::app::wasm::local::http::policy::{Denied, HttpPolicy};
+    use crate::app::wasm::local::http::HttpCtx;
+    use reqwest::Url;
+
+    #[test]
+    fn test_authorize_concurrent_requests() {
+        let permissions = HTTPPermissions {
+            allowed_hosts: vec!["https://api.example.com".to_string()],
+            max_concurrent_requests: 1,
+            allowed_methods: None,
+            allowed_paths: None,
+            max_request_body_size: None,
+            max_response_body_size: None,
+            max_requests: None,
+            timeout_seconds: None,
+            headers: None,
+        };
+        let mut ctx = HttpCtx::new(HttpPolicy::try_new(&permissions, Vec::new()).unwrap());
+        let url = Url::parse("https://api.example.com/").unwrap();
+
+        let in_flight = ctx.authorize("GET", &url, None).unwrap();
+        assert_eq!(
+            Some(Denied::ConcurrentRequests(1)),
+            ctx.authorize("GET", &url, Some(0)).err()
+        );
+        drop(in_flight);
+        assert!(ctx.authorize("GET", &url, None).is_ok());
+        assert_eq!(1, ctx.take_calls().len());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::app::model::HTTPPermissions;
+use anyhow::{anyhow, Context};
+use reqwest::Url;
+use std::fmt::{Debug, Display, Formatter};
+use std::time::Duration;
+
+/// Allowed host that lets modules connect to any host, as in wasi-experimental-http
+const ALLOW_ALL_HOSTS: &str = "insecure:allow-all";
+
+/// HttpPolicy decides which requests a module may send. Without HTTPPermissions, it denies all
+/// requests. Hosts are compared by their origin, i.e. their scheme, host name and port.
+#[derive(Clone, Debug, Default)]
+pub struct HttpPolicy {
+    allow_all_hosts: bool,
+    origins: Vec<String>,
+    methods: Option<Vec<String>>,
+    paths: Vec<(String, Vec<String>)>,
+    headers: Vec<InjectedHeader>,
+    pub max_concurrent_requests: Option<u32>,
+    pub max_request_body_size: Option<u64>,
+    pub max_response_body_size: Option<u64>,
+    pub max_requests: Option<u32>,
+    pub timeout: Option<Duration>,
+}
+
+/// InjectedHeader is added to every request to the origin host, which has to use https
+#[derive(Clone)]
+pub struct InjectedHeader {
+    pub host: String,
+    pub name: String,
+    pub value: String,
+}
+
+// The value is a credential
+impl Debug for InjectedHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InjectedHeader")
+            .field("host", &self.host)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// Denied explains why a request was not sent
+#[derive(Debug, PartialEq, Eq)]
+pub enum Denied {
+    Host(String),
+    Path(String),
+    Method(String),
+    RequestBodySize(u64),
+    Requests(u32),
+    ConcurrentRequests(u32),
+}
+
+impl Display for Denied {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Denied::Host(host) => write!(f, "Host {} is not allowed", host),
+            Denied::Path(path) => write!(f, "Path {} is not allowed", path),
+            Denied::Method(method) => write!(f, "Method {} is not allowed", method),
+            Denied::RequestBodySize(size) => {
+                write!(f, "Request body exceeds the limit of {} bytes", size)
+            }
+            Denied::Requests(requests) => {
+                write!(f, "Module exceeded the limit of {} requests", requests)
+            }
+            Denied::ConcurrentRequests(requests) => {
+                write!(
+                    f,
+                    "Module exceeded the limit of {} concurrent requests",
+                    requests
+                )
+            }
+        }
+    }
+}
+
+impl HttpPolicy {
+    pub fn try_new(
+        permissions: &HTTPPermissions,
+        headers: Vec<InjectedHeader>,
+    ) -> anyhow::Result<Self> {
+        let allow_all_hosts = permissions
+            .allowed_hosts
+            .iter()
+            .any(|host| host == ALLOW_ALL_HOSTS);
+        let origins = permissions
+            .allowed_hosts
+            .iter()
+            .filter(|host| *host != ALLOW_ALL_HOSTS)
+            .map(|host| origin(host))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        let methods = permissions
+            .allowed_methods
+            .as_ref()
+            .map(|methods| methods.iter().map(|method| method.to_uppercase()).collect());
+        let paths = permissions
+            .allowed_paths
+            .iter()
+            .flatten()
+            .map(|paths| Ok((origin(&paths.host)?, paths.prefixes.to_owned())))
+            .collect::<anyhow::Result<Vec<(String, Vec<String>)>>>()?;
+        let headers = headers
+            .into_iter()
+            .map(|header| {
+                let host = origin(&header.host)?;
+                // Credentials must not be sent in plain text
+                if !host.starts_with("https://") {
+                    return Err(anyhow!(format!(
+                        "Header {} can only be injected into requests to https hosts, not {}",
+                        header.name, header.host
+                    )));
+                }
+                Ok(InjectedHeader { host, ..header })
+            })
+            .collect::<anyhow::Result<Vec<InjectedHeader>>>()?;
+        Ok(HttpPolicy {
+            allow_all_hosts,
+            origins,
+            methods,
+            paths,
+            headers,
+            max_concurrent_requests: Some(permissions.max_concurrent_requests),
+            max_request_body_size: permissions.max_request_body_size.map(|size| size.bytes()),
+            max_response_body_size: permissions.max_response_body_size.map(|size| size.bytes()),
+            max_requests: permissions.max_requests,
+            timeout: permissions.timeout_seconds.map(Duration::from_secs),
+        })
+    }
+
+    /// Checks whether the module may send its request number `requests`, counting from 0
+    pub fn check(
+        &self,
+        method: &str,
+        url: &Url,
+        body_size: usize,
+        requests: u32,
+    ) -> Result<(), Denied> {
+        let origin = url.origin().ascii_serialization();
+        if !self.allow_all_hosts && !self.origins.contains(&origin) {
+            return Err(Denied::Host(origin));
+        }
+        if let Some(methods) = &self.methods {
+            if !methods.iter().any(|allowed| allowed == method) {
+                return Err(Denied::Method(method.to_owned()));
+            }
+        }
+        let mut prefixes = self
+            .paths
+            .iter()
+            .filter(|(paths_origin, _)| *paths_origin == origin)
+            .flat_map(|(_, prefixes)| prefixes)
+            .peekable();
+        if prefixes.peek().is_some() && !prefixes.any(|prefix| path_matches(url.path(), prefix)) {
+            return Err(Denied::Path(url.path().to_owned()));
+        }
+        if let Some(max_requests) = self.max_requests {
+            if requests >= max_requests {
+                return Err(Denied::Requests(max_requests));
+            }
+        }
+        if let Some(max_size) = self.max_request_body_size {
+            if body_size as u64 > max_size {
+                return Err(Denied::RequestBodySize(max_size));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the headers to add to a request to url. Headers are only injected into https
+    /// requests to the origin they were configured for.
+    pub fn headers<'a>(&'a self, url: &Url) -> impl Iterator<Item = &'a InjectedHeader> {
+        let origin = url.origin().ascii_serialization();
+        let https = url.scheme() == "https";
+        self.headers
+            .iter()
+            .filter(move |header| https && header.host == origin)
+    }
+}
+
+/// Hosts are configured as URLs like `https://example.com` or `http://localhost:8080`. Their
+/// origin is compared, with the default port of the scheme if the URL has none.
+fn origin(url: &str) -> anyhow::Result<String> {
+    let parsed = Url::parse(url).context(format!("Invalid host URL {}", url))?;
+    match parsed.origin() {
+        origin if origin.is_tuple() => Ok(origin.ascii_serialization()),
+        _ => Err(anyhow!(format!("Host URL {} has no host", url))),
+    }
+}
+
+/// A prefix matches whole path segments, so `/api` matches `/api/users` but not `/apis`
+fn path_matches(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::quantity::Quantity;
+    use crate::app::model::{HTTPPathPermission, HTTPPermissions};
+    use crate::app::wasm::local::http::policy::{Denied, HttpPolicy, InjectedHeader};
+    use reqwest::Url;
+
+    fn permissions() -> HTTPPermissions {
+        HTTPPermissions {
+            allowed_hosts: vec!["https://api.example.com".to_string()],
+            max_concurrent_requests: 8,
+            allowed_methods: Some(vec!["get".to_string()]),
+            allowed_paths: Some(vec![HTTPPathPermission {
+                host: "https://api.example.com".to_string(),
+                prefixes: vec!["/v1/users".to_string()],
+            }]),
+            max_request_body_size: Some(Quantity(4)),
+            max_response_body_size: None,
+            max_requests: Some(2),
+            timeout_seconds: None,
+            headers: None,
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = HttpPolicy::try_new(&permissions(), Vec::new()).unwrap();
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        assert_eq!(
+            Ok(()),
+            policy.check("GET", &url("https://API.example.com/v1/users/42"), 0, 0)
+        );
+        assert_eq!(
+            Ok(()),
+            policy.check("GET", &url("https://api.example.com:443/v1/users"), 0, 0)
+        );
+        assert_eq!(
+            Err(Denied::Host("https://example.com".to_string())),
+            policy.check("GET", &url("https://example.com/v1/users"), 0, 0)
+        );
+        assert_eq!(
+            Err(Denied::Host("http://api.example.com".to_string())),
+            policy.check("GET", &url("http://api.example.com/v1/users"), 0, 0)
+        );
+        assert_eq!(
+            Err(Denied::Host("https://api.example.com:8443".to_string())),
+            policy.check("GET", &url("https://api.example.com:8443/v1/users"), 0, 0)
+        );
+        assert_eq!(
+            Err(Denied::Method("POST".to_string())),
+            policy.check("POST", &url("https://api.example.com/v1/users"), 0, 0)
+        );
+        assert_eq!(
+            Err(Denied::Path("/v1/usersettings".to_string())),
+            policy.check("GET", &url("https://api.example.com/v1/usersettings"), 0, 0)
+        );
+        assert_eq!(
+            Err(Denied::Path("/v1/admin".to_string())),
+            policy.check(
+                "GET",
+                &url("https://api.example.com/v1/users/../admin"),
+                0,
+                0
+            )
+        );
+        assert_eq!(
+            Err(Denied::Requests(2)),
+            policy.check("GET", &url("https://api.example.com/v1/users"), 0, 2)
+        );
+        assert_eq!(
+            Err(Denied::RequestBodySize(4)),
+            policy.check("GET", &url("https://api.example.com/v1/users"), 5, 0)
+        );
+
+        let denied = HttpPolicy::default();
+        assert!(denied
+            .check("GET", &url("https://api.example.com/"), 0, 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_headers() {
+        let header = InjectedHeader {
+            host: "https://api.example.com".to_string(),
+            name: "Authorization".to_string(),
+            value: "Bearer s3cr3t".to_string(),
+        };
+        let policy = HttpPolicy::try_new(&permissions(), vec![header]).unwrap();
+
+        let headers: Vec<&str> = policy
+            .headers(&Url::parse("https://api.example.com/v1/users").unwrap())
+            .map(|header| header.value.as_str())
+            .collect();
+        assert_eq!(vec!["Bearer s3cr3t"], headers);
+        assert_eq!(
+            0,
+            policy
+                .headers(&Url::parse("https://example.com/").unwrap())
+                .count()
+        );
+        assert_eq!(
+            0,
+            policy
+                .headers(&Url::parse("https://api.example.com:8443/").unwrap())
+                .count()
+        );
+        assert!(!format!("{:?}", policy).contains("s3cr3t"));
+
+        let plain_text = InjectedHeader {
+            host: "http://api.example.com".to_string(),
+            name: "Authorization".to_string(),
+            value: "Bearer s3cr3t".to_string(),
+        };
+        assert!(HttpPolicy::try_new(&permissions(), vec![plain_text]).is_err());
+    }
+}
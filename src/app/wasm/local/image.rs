@@ -1,15 +1,169 @@
+use crate::app::model::registry::{RegistryCredential, RegistryCredentials};
+use crate::app::wasm::local::signature::Signature;
+use crate::app::wasm::local::source::verify_digest;
+use anyhow::anyhow;
+use oci_distribution::manifest::OciDescriptor;
+use oci_distribution::secrets::RegistryAuth;
+use oci_distribution::Reference;
 use std::str::FromStr;
 
+const ACCEPTED_MEDIA_TYPES: [&str; 3] = [
+    "application/vnd.module.wasm.content.layer.v1+wasm",
+    "application/vnd.wasm.content.layer.v1+wasm",
+    "application/vnd.oci.image.layer.v1.tar",
+];
+
+pub fn parse_reference(name: &str) -> anyhow::Result<Reference> {
+    Reference::from_str(name).map_err(|err| anyhow!(err).context("Parsing OCI reference"))
+}
+
+/// Returns the reference to the image with the given digest, e.g. `ghcr.io/org/mod@sha256:...`
+pub fn pinned_reference(reference: &Reference, digest: &str) -> String {
+    format!(
+        "{}/{}@{}",
+        reference.registry(),
+        reference.repository(),
+        digest
+    )
+}
+
+/// Asks the registry for the digest of the manifest the reference currently points to
+pub async fn resolve_digest<'a>(
+    reference: &Reference,
+    credentials: &RegistryCredentials,
+    allowed_insecure: &'a [&'a str],
+) -> anyhow::Result<String> {
+    let auth = registry_auth(credentials.get(reference.registry()));
+    let mut oci_client = oci_client(allowed_insecure);
+    Ok(oci_client.fetch_manifest_digest(reference, &auth).await?)
+}
+
+/// Pulls the Wasm layers of the image pinned by reference and verifies them against the digests
+/// in the manifest
 pub async fn fetch_oci_image<'a>(
-    name: &str,
+    reference: &Reference,
+    credentials: &RegistryCredentials,
     allowed_insecure: &'a [&'a str],
 ) -> anyhow::Result<Vec<u8>> {
     // Implementation kind-of based upon https://github.com/wasmCloud/wasmcloud-otp/blob/f6ae5c50a3c3cb2d0b923b6d92641c4b7f1d1d73/host_core/native/hostcore_wasmcloud_native/src/oci.rs
-    // TODO add caching
-    // TODO add auth support
-    let img = oci_distribution::Reference::from_str(name)?;
-    let auth = oci_distribution::secrets::RegistryAuth::Anonymous;
+    let auth = registry_auth(credentials.get(reference.registry()));
+    let mut oci_client = oci_client(allowed_insecure);
+    // TODO add pull timeout
+    let layers = pull_wasm_layers(&mut oci_client, reference, &auth).await?;
+    let mut content: Vec<u8> = Vec::new();
+    for layer in layers {
+        let mut data: Vec<u8> = Vec::new();
+        oci_client
+            .pull_blob(reference, &layer.digest, &mut data)
+            .await?;
+        verify_digest(&data, &layer.digest)?;
+        content.extend(data);
+    }
 
+    Ok(content)
+}
+
+/// Checks that content consists of the Wasm layers of the image pinned by reference, e.g. after
+/// reading it from the ModuleCache. Only the manifest is pulled.
+pub async fn verify_oci_image<'a>(
+    reference: &Reference,
+    content: &[u8],
+    credentials: &RegistryCredentials,
+    allowed_insecure: &'a [&'a str],
+) -> anyhow::Result<()> {
+    let auth = registry_auth(credentials.get(reference.registry()));
+    let mut oci_client = oci_client(allowed_insecure);
+    let mut rest = content;
+    for layer in pull_wasm_layers(&mut oci_client, reference, &auth).await? {
+        let size = usize::try_from(layer.size)
+            .ok()
+            .filter(|size| *size <= rest.len())
+            .ok_or_else(|| anyhow!(format!("Content is missing layer {}", layer.digest)))?;
+        let (data, tail) = rest.split_at(size);
+        verify_digest(data, &layer.digest)?;
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err(anyhow!(format!(
+            "Content is larger than the layers of {}",
+            reference
+        )));
+    }
+    Ok(())
+}
+
+/// Pulls the manifest of the image pinned by reference and returns its Wasm layers
+async fn pull_wasm_layers(
+    oci_client: &mut oci_distribution::Client,
+    reference: &Reference,
+    auth: &RegistryAuth,
+) -> anyhow::Result<Vec<OciDescriptor>> {
+    let expected_digest = reference
+        .digest()
+        .ok_or_else(|| anyhow!("Expected reference {} to be pinned by digest", reference))?;
+    let (manifest, digest) = oci_client.pull_image_manifest(reference, auth).await?;
+    if digest != expected_digest {
+        return Err(anyhow!(format!(
+            "Registry returned manifest {} instead of {}",
+            digest, expected_digest
+        )));
+    }
+    let layers = manifest
+        .layers
+        .into_iter()
+        .filter(|layer| ACCEPTED_MEDIA_TYPES.contains(&layer.media_type.as_str()))
+        .collect::<Vec<_>>();
+    if layers.is_empty() {
+        return Err(anyhow!(format!(
+            "Manifest {} does not contain a Wasm layer",
+            digest
+        )));
+    }
+    Ok(layers)
+}
+
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// Pulls the cosign signatures of the manifest with the given digest. Cosign stores them as
+/// layers of an image tagged `sha256-<hex>.sig` in the same repository.
+pub async fn fetch_signatures<'a>(
+    reference: &Reference,
+    digest: &str,
+    credentials: &RegistryCredentials,
+    allowed_insecure: &'a [&'a str],
+) -> anyhow::Result<Vec<Signature>> {
+    let signature_reference = parse_reference(&format!(
+        "{}/{}:{}.sig",
+        reference.registry(),
+        reference.repository(),
+        digest.replace(':', "-")
+    ))?;
+    let auth = registry_auth(credentials.get(reference.registry()));
+    let mut oci_client = oci_client(allowed_insecure);
+    let (manifest, _) = oci_client
+        .pull_image_manifest(&signature_reference, &auth)
+        .await?;
+    let mut signatures: Vec<Signature> = Vec::new();
+    for layer in &manifest.layers {
+        let signature = match layer
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(SIGNATURE_ANNOTATION))
+        {
+            Some(signature) => base64::decode(signature)?,
+            None => continue,
+        };
+        let mut payload: Vec<u8> = Vec::new();
+        oci_client
+            .pull_blob(&signature_reference, &layer.digest, &mut payload)
+            .await?;
+        verify_digest(&payload, &layer.digest)?;
+        signatures.push(Signature { payload, signature });
+    }
+    Ok(signatures)
+}
+
+fn oci_client<'a>(allowed_insecure: &'a [&'a str]) -> oci_distribution::Client {
     // Ideally, HttpsExcept would also accept a slice, but it doesn't...
     let allowed_insecure_owned = allowed_insecure
         .iter()
@@ -21,24 +175,124 @@ pub async fn fetch_oci_image<'a>(
         protocol,
         ..Default::default()
     };
-    let mut oci_client = oci_distribution::Client::new(config);
-    // TODO add pull timeout
-    let img_data = oci_client
-        .pull(
-            &img,
-            &auth,
-            vec![
-                "application/vnd.module.wasm.content.layer.v1+wasm",
-                "application/vnd.wasm.content.layer.v1+wasm",
-                "application/vnd.oci.image.layer.v1.tar",
-            ],
+    oci_distribution::Client::new(config)
+}
+
+/// Username sent along with PasswordToken credentials
+const TOKEN_USERNAME: &str = "<token>";
+
+fn registry_auth(credential: Option<&RegistryCredential>) -> RegistryAuth {
+    match credential {
+        Some(RegistryCredential::Basic { username, password }) => {
+            RegistryAuth::Basic(username.to_owned(), password.to_owned())
+        }
+        Some(RegistryCredential::PasswordToken(token)) => {
+            RegistryAuth::Basic(TOKEN_USERNAME.to_owned(), token.to_owned())
+        }
+        None => RegistryAuth::Anonymous,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::registry::RegistryCredentials;
+    use crate::app::wasm::local::image::{fetch_signatures, parse_reference};
+    use crate::app::wasm::local::signature::{generate_key, sign, SignatureVerifier};
+    use crate::app::wasm::local::source::sha256_digest;
+    use axum::routing::get;
+    use axum::Router;
+    use serde_json::json;
+    use std::net::TcpListener;
+
+    const DIGEST: &str = "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    /// Serves the signature image of DIGEST in `my-org/my-module` like an OCI registry, and
+    /// returns the host of the registry
+    fn serve_signatures(layers: serde_json::Value, blobs: Vec<(String, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": sha256_digest(b"{}"),
+                "size": 2
+            },
+            "layers": layers
+        })
+        .to_string();
+        let manifest_path = format!(
+            "/v2/my-org/my-module/manifests/{}.sig",
+            DIGEST.replace(':', "-")
+        );
+        let mut router = Router::new()
+            .route("/v2/", get(|| async { "{}" }))
+            .route(&manifest_path, get(move || async move { manifest }));
+        for (digest, blob) in blobs {
+            let blob_path = format!("/v2/my-org/my-module/blobs/{}", digest);
+            router = router.route(&blob_path, get(move || async move { blob }));
+        }
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        host
+    }
+
+    fn layer(digest: &str, signature: Option<&[u8]>) -> serde_json::Value {
+        let mut layer = json!({
+            "mediaType": "application/vnd.dev.cosign.simplesigning.v1+json",
+            "digest": digest,
+            "size": 0
+        });
+        if let Some(signature) = signature {
+            layer["annotations"] =
+                json!({ "dev.cosignproject.cosign/signature": base64::encode(signature) });
+        }
+        layer
+    }
+
+    #[tokio::test]
+    async fn test_fetch_signatures() {
+        let (private_key, public_key) = generate_key();
+        let signature = sign(&private_key, DIGEST);
+        let payload_digest = sha256_digest(&signature.payload);
+        let layers = json!([
+            layer(&payload_digest, Some(&signature.signature)),
+            // Layers without a signature are skipped without pulling them
+            layer(&sha256_digest(b"attestation"), None),
+        ]);
+        let host = serve_signatures(
+            layers.clone(),
+            vec![(payload_digest.clone(), signature.payload.clone())],
+        );
+        let reference = parse_reference(&format!("{}/my-org/my-module:v1", host)).unwrap();
+
+        let signatures = fetch_signatures(
+            &reference,
+            DIGEST,
+            &RegistryCredentials::default(),
+            &[host.as_str()],
         )
-        .await?;
-    let content = img_data
-        .layers
-        .iter()
-        .flat_map(|l| l.data.clone())
-        .collect::<Vec<_>>();
+        .await
+        .unwrap();
+        assert_eq!(1, signatures.len());
+        assert_eq!(signature.payload, signatures[0].payload);
+        assert_eq!(signature.signature, signatures[0].signature);
+        let verifier = SignatureVerifier::new(Vec::new(), vec![public_key]);
+        assert!(verifier.verify(DIGEST, &signatures).is_ok());
 
-    Ok(content)
+        // A registry that serves another payload than the manifest names is not trusted
+        let tampered = serve_signatures(layers, vec![(payload_digest, b"tampered".to_vec())]);
+        let reference = parse_reference(&format!("{}/my-org/my-module:v1", tampered)).unwrap();
+        assert!(fetch_signatures(
+            &reference,
+            DIGEST,
+            &RegistryCredentials::default(),
+            &[tampered.as_str()],
+        )
+        .await
+        .is_err());
+    }
 }
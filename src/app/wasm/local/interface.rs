@@ -1,16 +1,41 @@
-use crate::app::model::ModulePermissions;
+use crate::app::model::MountMode;
+use crate::app::wasm::local::http::audit::HttpCall;
+use crate::app::wasm::local::http::experimental;
+use crate::app::wasm::local::http::policy::HttpPolicy;
+use crate::app::wasm::local::http::{self, HttpCtx};
+use crate::app::wasm::local::kv::{self, KVCtx};
+use crate::app::wasm::local::logging::{self, LogCtx};
+use crate::app::wasm::local::mounts::Mount;
+use crate::app::wasm::local::output::{OutputPipe, OutputSink};
+use crate::app::wasm::local::progress::{self, ProgressCtx};
+use crate::app::wasm::local::secrets::{Redacted, Secrets, SECRETS_PLUGIN_PATH};
+use crate::app::wasm::local::wit::{self, WITModule, WitExports};
+use crate::app::wasm::{ExecutionLimits, LogOptions, Progress};
 use anyhow::{anyhow, Context};
 use async_trait::async_trait;
-use std::io::Cursor;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
 use tracing::{debug, info_span, Instrument};
-use wasi_common::pipe::WritePipe;
-use wasi_experimental_http_wasmtime::{HttpCtx, HttpState};
-use wasmtime::{Engine, Linker, Module, Store, TypedFunc};
-use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+use wasmtime::component::{self, Component, ResourceTable};
+use wasmtime::{
+    Engine, Instance, InstancePre, Linker, Module, Precompiled, ResourceLimiter, Store, TypedFunc,
+    DEFAULT_INSTANCE_LIMIT,
+};
+use wasmtime_wasi::bindings::{Command, CommandPre};
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtx, WasiCtxBuilder, WasiView};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
 use workflow_model::host::artifacts::ArtifactManager;
 use workflow_model::host::WorkingDir;
 use workflow_model::model::{
-    ArtifactRef, Outputs, Phase, PluginInvocation, PluginResult, S3ArtifactRepositoryConfig,
+    ArtifactRef, Outputs, Parameter, Phase, PluginInvocation, PluginResult,
+    S3ArtifactRepositoryConfig, OUTPUT_ARTIFACTS_PATH,
 };
 
 #[async_trait]
@@ -18,140 +43,569 @@ pub trait WorkflowPlugin {
     async fn run(&mut self, invocation: PluginInvocation) -> anyhow::Result<PluginResult>;
 }
 
-struct ModuleCtx {
-    pub wasi: WasiCtx,
-    pub http: HttpCtx,
+/// LimitExceeded is returned when a module execution exceeds one of its ExecutionLimits
+#[derive(Debug)]
+pub enum LimitExceeded {
+    Timeout(Duration),
+    Fuel(u64),
+    Memory(usize),
+    TableElements(u32),
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Timeout(timeout) => write!(
+                f,
+                "Module execution exceeded the time limit of {}s",
+                timeout.as_secs_f64()
+            ),
+            LimitExceeded::Fuel(fuel) => {
+                write!(f, "Module execution exceeded the fuel limit of {}", fuel)
+            }
+            LimitExceeded::Memory(bytes) => write!(
+                f,
+                "Module execution exceeded the memory limit of {} bytes",
+                bytes
+            ),
+            LimitExceeded::TableElements(elements) => write!(
+                f,
+                "Module execution exceeded the limit of {} table elements",
+                elements
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+pub struct ModuleCtx {
+    wasi: WasiP1Ctx,
+    http: HttpCtx,
+    kv: KVCtx,
+    log: LogCtx,
+    progress: ProgressCtx,
+    limiter: ModuleLimiter,
+}
+
+// Components use the same WASI context as modules, which wasmtime-wasi implements on top of it
+impl WasiView for ModuleCtx {
+    fn table(&mut self) -> &mut ResourceTable {
+        self.wasi.table()
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        self.wasi.ctx()
+    }
+}
+
+impl WasiHttpView for ModuleCtx {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        self.http.wasi()
+    }
+
+    fn table(&mut self) -> &mut ResourceTable {
+        self.wasi.table()
+    }
+
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        http::send_request(&mut self.http, request, config)
+    }
+}
+
+/// ModuleLimiter restricts the resources a module may allocate and remembers the limit that
+/// was exceeded, because the module only observes a failed allocation
+struct ModuleLimiter {
+    memory: Option<usize>,
+    table_elements: Option<u32>,
+    instances: Option<usize>,
+    exceeded: Option<LimitExceeded>,
+}
+
+impl ModuleLimiter {
+    fn new(limits: &ExecutionLimits) -> Self {
+        ModuleLimiter {
+            memory: limits.memory,
+            table_elements: limits.table_elements,
+            instances: limits.instances,
+            exceeded: None,
+        }
+    }
 }
 
-fn http_ctx_from_perms(perms: &Option<ModulePermissions>) -> HttpCtx {
-    let mut http_allowed_hosts: Option<Vec<String>> = None;
-    let mut http_max_concurrent_requests: Option<u32> = None;
-    if let Some(the_perms) = perms {
-        if let Some(http_perms) = &the_perms.http {
-            http_allowed_hosts = Some(http_perms.allowed_hosts.to_owned());
-            http_max_concurrent_requests = Some(http_perms.max_concurrent_requests)
+impl ResourceLimiter for ModuleLimiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        match self.memory {
+            Some(memory) if desired > memory => {
+                self.exceeded = Some(LimitExceeded::Memory(memory));
+                Ok(false)
+            }
+            _ => Ok(true),
         }
     }
-    HttpCtx {
-        allowed_hosts: http_allowed_hosts,
-        max_concurrent_requests: http_max_concurrent_requests,
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        desired: u32,
+        _maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        match self.table_elements {
+            Some(table_elements) if desired > table_elements => {
+                self.exceeded = Some(LimitExceeded::TableElements(table_elements));
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    fn instances(&self) -> usize {
+        self.instances.unwrap_or(DEFAULT_INSTANCE_LIMIT)
+    }
+}
+
+/// ModuleEnvironment holds the command line arguments, environment variables, secrets, mounts,
+/// HttpPolicy, KV store access and structured log of a module
+#[derive(Default)]
+pub struct ModuleEnvironment {
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub secrets: Secrets,
+    pub mounts: Vec<Mount>,
+    pub http: HttpPolicy,
+    pub kv: KVCtx,
+    pub log: LogCtx,
+}
+
+/// Name of the module in its arguments, where CLIs expect the name of the program
+const PROGRAM_NAME: &str = "module.wasm";
+
+/// Compiled is a module or a component, which is compiled from the preview2 component model
+/// binary format
+pub enum Compiled {
+    Module(Module),
+    Component(Component),
+}
+
+/// Linked is a compiled module or component whose imports are resolved, so that it can be
+/// instantiated without linking it again
+#[derive(Clone)]
+pub enum Linked {
+    Module(InstancePre<ModuleCtx>),
+    Component(component::InstancePre<ModuleCtx>),
+}
+
+/// Linkers defines the host functions for modules, which use WASI preview1 and the
+/// wasi-experimental-http shim, and for components, which use WASI preview2 and wasi:http
+pub struct Linkers {
+    module: Linker<ModuleCtx>,
+    component: component::Linker<ModuleCtx>,
+}
+
+/// Creates the Linkers for all modules of an engine. They only define host functions, so the
+/// instances they create can be instantiated in any Store of the engine.
+pub fn create_linker(engine: &Engine) -> anyhow::Result<Linkers> {
+    let mut module = Linker::new(engine);
+    preview1::add_to_linker_async(&mut module, |ctx: &mut ModuleCtx| &mut ctx.wasi)?;
+    experimental::add_to_linker(&mut module, |ctx: &mut ModuleCtx| &mut ctx.http)?;
+    kv::host::add_to_linker(&mut module, |ctx: &mut ModuleCtx| &mut ctx.kv)?;
+    logging::add_to_linker(&mut module, |ctx: &mut ModuleCtx| &mut ctx.log)?;
+    progress::add_to_linker(&mut module, |ctx: &mut ModuleCtx| &mut ctx.progress)?;
+    let mut component = component::Linker::new(engine);
+    wasmtime_wasi::add_to_linker_async(&mut component)?;
+    wasmtime_wasi_http::add_only_http_to_linker_async(&mut component)?;
+    wit::kv::add_to_linker(&mut component, |ctx: &mut ModuleCtx| &mut ctx.kv)?;
+    wit::log::add_to_linker(&mut component, |ctx: &mut ModuleCtx| &mut ctx.log)?;
+    wit::progress::add_to_linker(&mut component, |ctx: &mut ModuleCtx| &mut ctx.progress)?;
+    Ok(Linkers { module, component })
+}
+
+/// Components share the magic number of modules, but have a different layer in their header
+fn is_component(wasm: &[u8]) -> bool {
+    wasm.starts_with(b"\0asm") && wasm.get(6..8) == Some(&[1, 0])
+}
+
+/// Returns a fingerprint of the wasmtime version and the settings of the engine. Modules
+/// precompiled by an engine with another fingerprint must not be deserialized. Unlike the
+/// DefaultHasher, SHA-256 is stable across Rust versions and plugin replicas.
+pub fn engine_fingerprint(engine: &Engine) -> String {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    format!("{:x}", hasher.0.finalize())
+}
+
+/// Sha256Hasher feeds the data that a Hash implementation writes into SHA-256
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn finish(&self) -> u64 {
+        unreachable!("the digest is read from the inner Sha256")
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+}
+
+/// Compiles a module or component, so that it can be stored in the ModuleCache
+pub fn precompile(engine: &Engine, wasm: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match is_component(wasm) {
+        true => engine.precompile_component(wasm),
+        false => engine.precompile_module(wasm),
+    }
+}
+
+/// Deserializes a module or component that was compiled with precompile
+pub fn deserialize(engine: &Engine, precompiled: &[u8]) -> anyhow::Result<Compiled> {
+    match engine.detect_precompiled(precompiled) {
+        Some(Precompiled::Module) => Ok(Compiled::Module(unsafe {
+            Module::deserialize(engine, precompiled)?
+        })),
+        Some(Precompiled::Component) => Ok(Compiled::Component(unsafe {
+            Component::deserialize(engine, precompiled)?
+        })),
+        None => Err(anyhow!("Not a module or component precompiled by wasmtime")),
+    }
+}
+
+/// Resolves the imports of the module or component
+pub fn instantiate_pre(linker: &Linkers, compiled: &Compiled) -> anyhow::Result<Linked> {
+    match compiled {
+        Compiled::Module(module) => Ok(Linked::Module(linker.module.instantiate_pre(module)?)),
+        Compiled::Component(component) => Ok(Linked::Component(
+            linker.component.instantiate_pre(component)?,
+        )),
     }
 }
 
-fn setup_module(
+/// Instantiates the module or component in the store of instance and returns it as
+/// WorkflowPlugin. Implementations of the WIT workflow interface are preferred over WASI
+/// commands.
+pub async fn instantiate(
+    linked: &Linked,
+    mut instance: ModuleInstance,
+) -> anyhow::Result<Box<dyn WorkflowPlugin + Send>> {
+    match linked {
+        Linked::Module(instance_pre) => {
+            let wasm = instance_pre.instantiate_async(instance.store()).await?;
+            if wit::is_wit_module(instance.store(), &wasm) {
+                let module = WITModule::try_from_module(instance, wasm)
+                    .await
+                    .context("Creating WIT module failed")?;
+                Ok(Box::new(module))
+            } else {
+                let module = WASIModule::try_from_module(instance, wasm)
+                    .context("Creating WASI module failed")?;
+                Ok(Box::new(module))
+            }
+        }
+        Linked::Component(instance_pre) => {
+            if let Ok(plugin_pre) = wit::WorkflowPluginPre::new(instance_pre.clone()) {
+                let module = WITModule::try_from_component(instance, &plugin_pre)
+                    .await
+                    .context("Creating WIT component failed")?;
+                return Ok(Box::new(module));
+            }
+            let command_pre = CommandPre::new(instance_pre.clone()).context(
+                "Component implements neither the WIT workflow interface nor wasi:cli/run",
+            )?;
+            let module = WASIModule::try_from_component(instance, &command_pre)
+                .await
+                .context("Creating WASI component failed")?;
+            Ok(Box::new(module))
+        }
+    }
+}
+
+fn setup_store(
     engine: &Engine,
-    perms: &Option<ModulePermissions>,
+    limits: &ExecutionLimits,
+    environment: &ModuleEnvironment,
+    log: LogCtx,
     working_dir: &WorkingDir,
-) -> anyhow::Result<(Linker<ModuleCtx>, Store<ModuleCtx>)> {
-    let mut linker = Linker::new(engine);
-    wasmtime_wasi::add_to_linker(&mut linker, |ctx: &mut ModuleCtx| &mut ctx.wasi)?;
-    let preopen_working_dir =
-        cap_std::fs::Dir::open_ambient_dir(working_dir.path(), cap_std::ambient_authority())?;
-    let mut wasi = WasiCtxBuilder::new()
+    sys_output: &SysOutput,
+) -> anyhow::Result<Store<ModuleCtx>> {
+    let mut args = vec![PROGRAM_NAME.to_string()];
+    args.extend(environment.args.iter().cloned());
+    let mut wasi = WasiCtxBuilder::new();
+    wasi.args(&args)
+        .envs(&environment.env)
+        .envs(environment.secrets.env())
+        .stdout(sys_output.stdout.clone())
+        .stderr(sys_output.stderr.clone())
         .preopened_dir(
-            preopen_working_dir,
+            working_dir.path(),
             workflow_model::model::WORKING_DIR_PLUGIN_PATH,
-        )?
-        .build();
-    let stdout = WritePipe::new_in_memory();
-    let stderr = WritePipe::new_in_memory();
-    wasi.set_stdout(Box::new(stdout));
-    wasi.set_stderr(Box::new(stderr));
-    let http_ctx = http_ctx_from_perms(perms);
-    debug!(
-        allowed_hosts = ?http_ctx.allowed_hosts,
-        max_concurrent_requests = ?http_ctx.max_concurrent_requests,
-        "WASI HTTP Settings"
-    );
-    let http = HttpState::new()?;
-    http.add_to_linker(&mut linker, |ctx: &ModuleCtx| -> &HttpCtx { &ctx.http })?;
-    let store = Store::new(
+            DirPerms::all(),
+            FilePerms::all(),
+        )?;
+    if let Some(dir) = environment.secrets.dir() {
+        preopen_dir(&mut wasi, dir, SECRETS_PLUGIN_PATH, MountMode::ReadOnly)?;
+    }
+    for mount in &environment.mounts {
+        preopen_dir(&mut wasi, &mount.host_path, &mount.guest_path, mount.mode)
+            .context(format!("Opening mount {:?}", mount.host_path))?;
+    }
+    debug!(policy = ?environment.http, "WASI HTTP Settings");
+    let mut store = Store::new(
         engine,
         ModuleCtx {
-            wasi,
-            http: http_ctx,
+            wasi: wasi.build_p1(),
+            http: HttpCtx::new(environment.http.clone()),
+            kv: environment.kv.clone(),
+            log,
+            progress: ProgressCtx::new(environment.secrets.redactor()),
+            limiter: ModuleLimiter::new(limits),
         },
     );
-    Ok((linker, store))
-}
-
-type SysOutput = (WritePipe<Cursor<Vec<u8>>>, WritePipe<Cursor<Vec<u8>>>);
-
-fn prepare_sys_output(wasi: &mut WasiCtx) -> SysOutput {
-    let stdout = WritePipe::new_in_memory();
-    let stderr = WritePipe::new_in_memory();
-    wasi.set_stdout(Box::new(stdout.clone()));
-    wasi.set_stderr(Box::new(stderr.clone()));
-    (stdout, stderr)
-}
-
-fn retrieve_sys_output(
-    stdout: WritePipe<Cursor<Vec<u8>>>,
-    stderr: WritePipe<Cursor<Vec<u8>>>,
-) -> anyhow::Result<(String, String)> {
-    let stdout: Vec<u8> = stdout
-        .try_into_inner()
-        .expect("sole remaining reference to WritePipe")
-        .into_inner();
-    let stdout =
-        String::from_utf8(stdout).map_err(|err| anyhow!(err).context("Parsing stdout as UTF-8"))?;
-    let stderr: Vec<u8> = stderr
-        .try_into_inner()
-        .expect("sole remaining reference to WritePipe")
-        .into_inner();
-    let stderr =
-        String::from_utf8(stderr).map_err(|err| anyhow!(err).context("Parsing stderr as UTF-8"))?;
+    store.limiter(|ctx| &mut ctx.limiter);
+    // Fuel consumption is enabled for every module, so modules without a limit get all the fuel
+    store.set_fuel(limits.fuel.unwrap_or(u64::MAX))?;
+    store.epoch_deadline_async_yield_and_update(1);
+    Ok(store)
+}
+
+/// Preopens dir at guest_path. In read-only mode, the module can read but not modify its contents.
+fn preopen_dir(
+    wasi: &mut WasiCtxBuilder,
+    dir: &Path,
+    guest_path: &str,
+    mode: MountMode,
+) -> anyhow::Result<()> {
+    let (dir_perms, file_perms) = match mode {
+        MountMode::ReadOnly => (DirPerms::READ, FilePerms::READ),
+        MountMode::ReadWrite => (DirPerms::all(), FilePerms::all()),
+    };
+    wasi.preopened_dir(dir, guest_path, dir_perms, file_perms)?;
+    Ok(())
+}
+
+/// SysOutput holds the pipes for stdout and stderr. Their files are in a dir of their own, which
+/// is not preopened, so the module can only write to them through the size-capped pipes.
+struct SysOutput {
+    _dir: TempDir,
+    stdout: OutputPipe,
+    stderr: OutputPipe,
+}
+
+fn create_sys_output(max_size: usize) -> anyhow::Result<SysOutput> {
+    let dir = TempDir::new().context("Creating output dir")?;
+    let stdout = OutputSink::create(dir.path().join(STDOUT_FILE_NAME), max_size as u64)
+        .context("Creating stdout file")?;
+    let stderr = OutputSink::create(dir.path().join(STDERR_FILE_NAME), max_size as u64)
+        .context("Creating stderr file")?;
+    Ok(SysOutput {
+        _dir: dir,
+        stdout: OutputPipe::new(stdout),
+        stderr: OutputPipe::new(stderr),
+    })
+}
+
+fn retrieve_sys_output(sys_output: &SysOutput) -> anyhow::Result<(String, String)> {
+    let stdout = sys_output.stdout.contents().context("Reading stdout")?;
+    let stderr = sys_output.stderr.contents().context("Reading stderr")?;
     Ok((stdout, stderr))
 }
 
-pub struct WASIModule {
+/// Emits every line the module printed as an event in the span of the request, which names the
+/// workflow and template
+fn trace_sys_output(stdout: &str, stderr: &str, secrets: &Secrets) {
+    for line in stdout.lines() {
+        tracing::info!(target: "wasm_module", stream = "stdout", "{}", secrets.redact(line));
+    }
+    for line in stderr.lines() {
+        tracing::info!(target: "wasm_module", stream = "stderr", "{}", secrets.redact(line));
+    }
+}
+
+/// Adds the exceeded limit to the error of the module. The last reported progress is attached below
+/// the limit, so the limit stays the message of the error.
+fn limit_error(
+    err: anyhow::Error,
+    limit_exceeded: LimitExceeded,
+    progress: Option<Progress>,
+) -> anyhow::Error {
+    match progress {
+        Some(progress) => err.context(progress).context(limit_exceeded),
+        None => err.context(limit_exceeded),
+    }
+}
+
+const STDOUT_FILE_NAME: &str = "stdout.log";
+const STDERR_FILE_NAME: &str = "stderr.log";
+const LOG_ARTIFACT_NAME: &str = "logs";
+const LOG_ARTIFACT_PATH: &str = "wasm-module.log";
+const HTTP_CALLS_PARAMETER_NAME: &str = "http_calls";
+
+/// ModuleInstance is the store of a module with its working dir and environment, in which the
+/// module is instantiated and run through an Entrypoint
+pub struct ModuleInstance {
     working_dir: WorkingDir,
+    limits: ExecutionLimits,
+    logs: LogOptions,
+    /// Kept until the module is done, since it removes the secrets dir when dropped
+    secrets: Secrets,
     artifact_repo_config: Option<S3ArtifactRepositoryConfig>,
     store: Option<Store<ModuleCtx>>,
-    workflow: TypedFunc<(), ()>,
+    sys_output: Option<SysOutput>,
 }
 
-impl WASIModule {
+/// Entrypoint is the way a module receives its invocation and returns its result
+pub enum Entrypoint<'a> {
+    /// WASI commands read the invocation from and write the result to their working dir
+    Command(&'a Start),
+    Wit(&'a WitExports),
+}
+
+/// Start is the export that runs a WASI command
+pub enum Start {
+    Module(TypedFunc<(), ()>),
+    Component(Command),
+}
+
+impl Start {
+    async fn call(&self, store: &mut Store<ModuleCtx>) -> anyhow::Result<()> {
+        match self {
+            Start::Module(start) => start.call_async(store, ()).await,
+            Start::Component(command) => command
+                .wasi_cli_run()
+                .call_run(store)
+                .await?
+                .map_err(|_| anyhow!("Component exited with an error")),
+        }
+    }
+}
+
+impl ModuleInstance {
     pub async fn try_new(
         engine: &Engine,
-        module: &Module,
-        perms: &Option<ModulePermissions>,
+        limits: ExecutionLimits,
+        logs: LogOptions,
+        mut environment: ModuleEnvironment,
         artifact_repo_config: Option<S3ArtifactRepositoryConfig>,
     ) -> anyhow::Result<Self> {
         let working_dir = WorkingDir::try_new().await?;
-        let (mut linker, mut store) = setup_module(engine, perms, &working_dir)?;
-        linker.module_async(&mut store, "", module).await?;
-        let workflow = linker
-            .get_default(&mut store, "")?
-            .typed::<(), (), _>(&store)?;
+        let sys_output = create_sys_output(logs.max_size)?;
+        let mut log = std::mem::take(&mut environment.log);
+        if logs.artifact {
+            log.collect(logs.max_size);
+        }
+        let store = setup_store(
+            engine,
+            &limits,
+            &environment,
+            log,
+            &working_dir,
+            &sys_output,
+        )?;
 
-        Ok(Self::new(
+        Ok(ModuleInstance {
             working_dir,
+            limits,
+            logs,
+            secrets: environment.secrets,
             artifact_repo_config,
-            store,
-            workflow,
-        ))
+            store: Some(store),
+            sys_output: Some(sys_output),
+        })
     }
 
-    fn new(
-        working_dir: WorkingDir,
-        artifact_repo_config: Option<S3ArtifactRepositoryConfig>,
-        store: Store<ModuleCtx>,
-        workflow: TypedFunc<(), ()>,
-    ) -> Self {
-        WASIModule {
-            working_dir,
-            artifact_repo_config,
-            workflow,
-            store: Some(store),
+    /// Returns the store for instantiating the module and looking up its exports, until the
+    /// module is run
+    pub fn store(&mut self) -> &mut Store<ModuleCtx> {
+        self.store.as_mut().expect("present store")
+    }
+
+    /// Adds the HTTP requests of the module to the outputs of the result if enabled in LogOptions
+    fn add_http_calls(
+        &self,
+        result: &mut PluginResult,
+        calls: Vec<HttpCall>,
+    ) -> anyhow::Result<()> {
+        if !self.logs.http_calls {
+            return Ok(());
         }
+        result.outputs.parameters.push(Parameter {
+            name: HTTP_CALLS_PARAMETER_NAME.to_string(),
+            value: serde_json::to_value(calls).context("Encoding HTTP calls")?,
+        });
+        Ok(())
     }
-}
 
-#[async_trait]
-impl WorkflowPlugin for WASIModule {
-    async fn run(&mut self, invocation: PluginInvocation) -> anyhow::Result<PluginResult> {
-        debug!(?invocation, "Running WASIModule");
+    /// Adds stdout and stderr to the outputs of the result as selected in the LogOptions. The
+    /// collected log records are only added to the log artifact.
+    async fn add_sys_output(
+        &mut self,
+        result: &mut PluginResult,
+        (stdout, stderr): (String, String),
+        log_records: String,
+        manager: &Option<ArtifactManager>,
+        workflow_name: &str,
+    ) -> anyhow::Result<()> {
+        if !self.logs.parameters && !self.logs.artifact {
+            return Ok(());
+        }
+        // Modules may print the secrets they were given
+        let stdout = self.secrets.redact(&stdout);
+        let stderr = self.secrets.redact(&stderr);
+        if self.logs.artifact {
+            match manager {
+                Some(manager) => {
+                    let path = self
+                        .working_dir
+                        .path()
+                        .join(OUTPUT_ARTIFACTS_PATH)
+                        .join(LOG_ARTIFACT_PATH);
+                    let mut log =
+                        format!("=== stdout ===\n{}\n=== stderr ===\n{}\n", stdout, stderr);
+                    if !log_records.is_empty() {
+                        log.push_str(&format!("=== log ===\n{}", log_records));
+                    }
+                    tokio::fs::write(&path, log)
+                        .await
+                        .context("Writing log artifact")?;
+                    let artifact = ArtifactRef {
+                        name: LOG_ARTIFACT_NAME.to_string(),
+                        path: LOG_ARTIFACT_PATH.to_string(),
+                        s3: None,
+                    };
+                    let artifact = manager
+                        .upload(&self.working_dir, workflow_name, &artifact)
+                        .await
+                        .context("Uploading log artifact")?;
+                    result.outputs.artifacts.push(artifact);
+                }
+                None => debug!("S3ArtifactRepositoryConfig absent, not uploading log artifact"),
+            }
+        }
+        if self.logs.parameters {
+            result.outputs.parameters.push(Parameter {
+                name: "stdout".to_string(),
+                value: serde_json::Value::String(stdout),
+            });
+            result.outputs.parameters.push(Parameter {
+                name: "stderr".to_string(),
+                value: serde_json::Value::String(stderr),
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn run(
+        &mut self,
+        invocation: PluginInvocation,
+        entrypoint: Entrypoint<'_>,
+    ) -> anyhow::Result<PluginResult> {
+        debug!(invocation = ?Redacted(&invocation, &self.secrets), "Running module");
         self.working_dir.set_input(&invocation)?;
         let mut manager: Option<ArtifactManager> = None;
         if let Some(config) = &self.artifact_repo_config {
@@ -160,47 +614,177 @@ impl WorkflowPlugin for WASIModule {
             debug!("S3ArtifactRepositoryConfig absent, ignoring artifacts")
         }
         if let Some(manager) = &manager {
-            for artifact in invocation.artifacts {
+            for artifact in &invocation.artifacts {
                 manager
-                    .download(&self.working_dir, &artifact)
+                    .download(&self.working_dir, artifact)
                     .await
                     .context(format!("Downloading artifact {:?}", artifact))?;
             }
         }
-        let mut store = self.store.as_mut().expect("present store");
-        let (stdout, stderr) = prepare_sys_output(&mut store.data_mut().wasi);
+        let sys_output = self.sys_output.take().expect("present sys output");
+        let store = self.store.as_mut().expect("present store");
 
         let span = info_span!("wasm.execute_mod");
-        let result = self.workflow.call_async(&mut store, ()).instrument(span);
-        match result.await {
-            Ok(_) => {
-                self.store = None;
-                let (stdout, stderr) = retrieve_sys_output(stdout, stderr)?;
-                debug!(?stdout, ?stderr, "Module Output");
-                let mut result = self.working_dir.result()?;
-                if let Some(manager) = &manager {
-                    let mut artifacts: Vec<ArtifactRef> = Vec::new();
-                    for artifact in result.outputs.artifacts {
-                        let this_ref = manager
-                            .upload(&self.working_dir, &invocation.workflow_name, &artifact)
-                            .await?;
-                        artifacts.push(this_ref);
+        let call = async {
+            match entrypoint {
+                Entrypoint::Command(start) => start.call(&mut *store).await.map(|_| None),
+                Entrypoint::Wit(exports) => exports.run(&mut *store, &invocation).await.map(Some),
+            }
+        }
+        .instrument(span);
+        let result: anyhow::Result<Option<PluginResult>> = match self.limits.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, call).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!(LimitExceeded::Timeout(timeout))),
+            },
+            None => call.await,
+        };
+        let http_calls = store.data_mut().http.take_calls();
+        let log_records = store.data_mut().log.take_records();
+        let progress = store.data_mut().progress.take();
+        let fuel_remaining = store.get_fuel().unwrap_or_default();
+        let limit_exceeded = store.data_mut().limiter.exceeded.take();
+        let reported = progress.clone();
+        let outcome: anyhow::Result<PluginResult> = async {
+            match result {
+                Ok(result) => {
+                    self.store = None;
+                    let (stdout, stderr) = retrieve_sys_output(&sys_output)?;
+                    trace_sys_output(&stdout, &stderr, &self.secrets);
+                    let mut result = match result {
+                        Some(result) => result,
+                        None => self.working_dir.result()?,
+                    };
+                    if result.progress.is_none() {
+                        result.progress = progress.as_ref().map(Progress::fraction);
                     }
-                    result.outputs.artifacts = artifacts;
-                }
+                    if let Some(manager) = &manager {
+                        let mut artifacts: Vec<ArtifactRef> = Vec::new();
+                        for artifact in result.outputs.artifacts {
+                            let this_ref = manager
+                                .upload(&self.working_dir, &invocation.workflow_name, &artifact)
+                                .await?;
+                            artifacts.push(this_ref);
+                        }
+                        result.outputs.artifacts = artifacts;
+                    }
+                    self.add_sys_output(
+                        &mut result,
+                        (stdout, stderr),
+                        log_records,
+                        &manager,
+                        &invocation.workflow_name,
+                    )
+                    .await?;
+                    self.add_http_calls(&mut result, http_calls)?;
 
-                Ok(result)
-            }
-            Err(e) => {
-                self.store = None;
-                let (stdout, stderr) = retrieve_sys_output(stdout, stderr)?;
-                debug!(?stdout, ?stderr, "Module Output");
-                Ok(PluginResult {
-                    phase: Phase::Failed,
-                    message: e.to_string(),
-                    outputs: Outputs::default(),
-                })
+                    Ok(result)
+                }
+                Err(e) => {
+                    self.store = None;
+                    let (stdout, stderr) = retrieve_sys_output(&sys_output)?;
+                    trace_sys_output(&stdout, &stderr, &self.secrets);
+                    let e = match e.downcast::<LimitExceeded>() {
+                        Ok(limit_exceeded) => {
+                            return Err(match progress {
+                                Some(progress) => anyhow!(progress).context(limit_exceeded),
+                                None => anyhow!(limit_exceeded),
+                            })
+                        }
+                        Err(e) => e,
+                    };
+                    if let Some(limit_exceeded) = limit_exceeded {
+                        return Err(limit_error(e, limit_exceeded, progress));
+                    }
+                    if let Some(fuel) = self.limits.fuel {
+                        if fuel_remaining == 0 {
+                            return Err(limit_error(e, LimitExceeded::Fuel(fuel), progress));
+                        }
+                    }
+                    let mut result = PluginResult {
+                        phase: Phase::Failed,
+                        message: e.to_string(),
+                        outputs: Outputs::default(),
+                        progress: progress.as_ref().map(Progress::fraction),
+                    };
+                    self.add_sys_output(
+                        &mut result,
+                        (stdout, stderr),
+                        log_records,
+                        &manager,
+                        &invocation.workflow_name,
+                    )
+                    .await?;
+                    self.add_http_calls(&mut result, http_calls)?;
+                    Ok(result)
+                }
             }
         }
+        .await;
+        // Errors after the module ran, e.g. of a missing result or a failed upload, keep the
+        // progress as well
+        outcome.map_err(|err| match (Progress::of(&err), reported) {
+            (None, Some(progress)) => err.context(progress),
+            _ => err,
+        })
+    }
+}
+
+/// WASIModule runs WASI commands, which exchange the invocation and result as files
+pub struct WASIModule {
+    instance: ModuleInstance,
+    start: Start,
+}
+
+impl WASIModule {
+    pub fn try_from_module(mut instance: ModuleInstance, wasm: Instance) -> anyhow::Result<Self> {
+        let start = wasm.get_typed_func::<(), ()>(instance.store(), "_start")?;
+        Ok(WASIModule {
+            instance,
+            start: Start::Module(start),
+        })
+    }
+
+    pub async fn try_from_component(
+        mut instance: ModuleInstance,
+        command_pre: &CommandPre<ModuleCtx>,
+    ) -> anyhow::Result<Self> {
+        let command = command_pre.instantiate_async(instance.store()).await?;
+        Ok(WASIModule {
+            instance,
+            start: Start::Component(command),
+        })
+    }
+}
+
+#[async_trait]
+impl WorkflowPlugin for WASIModule {
+    async fn run(&mut self, invocation: PluginInvocation) -> anyhow::Result<PluginResult> {
+        self.instance
+            .run(invocation, Entrypoint::Command(&self.start))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::interface::{engine_fingerprint, is_component};
+    use wasmtime::{Config, Engine};
+
+    #[test]
+    fn test_is_component() {
+        assert!(!is_component(b"\0asm\x01\0\0\0"));
+        assert!(is_component(b"\0asm\x0d\0\x01\0"));
+        assert!(!is_component(b"\0asm"));
+        assert!(!is_component(b"(module)"));
+    }
+
+    #[test]
+    fn test_engine_fingerprint() {
+        let fingerprint = engine_fingerprint(&Engine::default());
+        assert_eq!(64, fingerprint.len());
+        assert_eq!(fingerprint, engine_fingerprint(&Engine::default()));
+        let fuel = Engine::new(Config::new().consume_fuel(true)).unwrap();
+        assert_ne!(fingerprint, engine_fingerprint(&fuel));
     }
 }
@@ -0,0 +1,238 @@
+use crate::app::wasm::local::kv::{Entry, KVStore};
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{Api, PostParams};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of times an update is retried when another replica of the plugin changed the ConfigMap
+/// at the same time
+const CONFLICT_RETRIES: usize = 5;
+
+/// ConfigMapKVStore keeps the entries in a ConfigMap, so they are shared by all replicas of the
+/// plugin and survive restarts. ConfigMaps are limited to 1 MiB, so it is meant for small state.
+/// Entries that cannot be decoded are treated as absent and removed by the next update.
+pub struct ConfigMapKVStore {
+    config_maps: Api<ConfigMap>,
+    name: String,
+}
+
+/// StoredEntry is the JSON representation of an Entry in the ConfigMap. Keys of ConfigMaps are
+/// limited in length and characters, so they are derived from a hash and the namespace and key
+/// are stored in the entry.
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    namespace: String,
+    key: String,
+    /// Base64-encoded value
+    value: String,
+    /// Seconds since the Unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+impl ConfigMapKVStore {
+    pub fn new(client: kube::Client, ns: Option<&str>, name: &str) -> Self {
+        let config_maps = match ns {
+            Some(ns) => Api::namespaced(client, ns),
+            None => Api::default_namespaced(client),
+        };
+        ConfigMapKVStore {
+            config_maps,
+            name: name.to_owned(),
+        }
+    }
+
+    async fn load(&self) -> anyhow::Result<Option<ConfigMap>> {
+        match self.config_maps.get(&self.name).await {
+            Ok(config_map) => Ok(Some(config_map)),
+            Err(kube::Error::Api(err)) if err.code == 404 => Ok(None),
+            Err(err) => Err(anyhow!(err).context(format!("Getting ConfigMap {}", self.name))),
+        }
+    }
+
+    async fn entries(&self) -> anyhow::Result<BTreeMap<String, String>> {
+        Ok(self
+            .load()
+            .await?
+            .and_then(|config_map| config_map.data)
+            .unwrap_or_default())
+    }
+
+    /// Applies update to the data of the ConfigMap, creating it if it does not exist. The update
+    /// is retried if the ConfigMap was changed since it was read.
+    async fn update(&self, update: impl Fn(&mut BTreeMap<String, String>)) -> anyhow::Result<()> {
+        for _ in 0..CONFLICT_RETRIES {
+            let existing = self.load().await?;
+            let exists = existing.is_some();
+            let mut config_map = existing.unwrap_or_else(|| ConfigMap {
+                metadata: ObjectMeta {
+                    name: Some(self.name.to_owned()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+            let mut data = config_map.data.take().unwrap_or_default();
+            let now = SystemTime::now();
+            data.retain(|_, entry| matches!(decode_entry(entry), Some((_, _, entry)) if !entry.expired(now)));
+            update(&mut data);
+            config_map.data = Some(data);
+            let result = match exists {
+                true => {
+                    self.config_maps
+                        .replace(&self.name, &PostParams::default(), &config_map)
+                        .await
+                }
+                false => {
+                    self.config_maps
+                        .create(&PostParams::default(), &config_map)
+                        .await
+                }
+            };
+            match result {
+                Ok(_) => return Ok(()),
+                Err(kube::Error::Api(err)) if err.code == 409 => {
+                    tracing::debug!("ConfigMap {} changed concurrently, retrying", self.name)
+                }
+                Err(err) => {
+                    return Err(anyhow!(err).context(format!("Updating ConfigMap {}", self.name)))
+                }
+            }
+        }
+        Err(anyhow!(format!(
+            "ConfigMap {} changed concurrently {} times",
+            self.name, CONFLICT_RETRIES
+        )))
+    }
+}
+
+#[async_trait]
+impl KVStore for ConfigMapKVStore {
+    async fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let entries = self.entries().await?;
+        match entries
+            .get(&encode_key(namespace, key))
+            .and_then(|entry| decode_entry(entry))
+        {
+            Some((_, _, entry)) if !entry.expired(SystemTime::now()) => Ok(Some(entry.value)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    ) -> anyhow::Result<()> {
+        let entry = encode_entry(namespace, key, &Entry { value, expires_at })?;
+        let key = encode_key(namespace, key);
+        self.update(|data| {
+            data.insert(key.to_owned(), entry.to_owned());
+        })
+        .await
+    }
+
+    async fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()> {
+        let key = encode_key(namespace, key);
+        self.update(|data| {
+            data.remove(&key);
+        })
+        .await
+    }
+
+    async fn list(&self, namespace: &str, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let now = SystemTime::now();
+        let mut keys = self
+            .entries()
+            .await?
+            .values()
+            .filter_map(|entry| decode_entry(entry))
+            .filter(|(ns, key, entry)| {
+                ns == namespace && key.starts_with(prefix) && !entry.expired(now)
+            })
+            .map(|(_, key, _)| key)
+            .collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// Keys of ConfigMaps may only contain alphanumeric characters, `-`, `_` and `.` and are limited
+/// to 253 characters, so the namespace and key are hashed
+fn encode_key(namespace: &str, key: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{}/{}", namespace, key)))
+}
+
+fn encode_entry(namespace: &str, key: &str, entry: &Entry) -> anyhow::Result<String> {
+    let expires_at = entry
+        .expires_at
+        .map(|expires_at| expires_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()))
+        .transpose()?;
+    let stored = StoredEntry {
+        namespace: namespace.to_owned(),
+        key: key.to_owned(),
+        value: base64::encode(&entry.value),
+        expires_at,
+    };
+    Ok(serde_json::to_string(&stored)?)
+}
+
+/// Returns the namespace, key and entry, or None if the entry cannot be decoded
+fn decode_entry(entry: &str) -> Option<(String, String, Entry)> {
+    let decoded = serde_json::from_str::<StoredEntry>(entry)
+        .context("Parsing KV entry")
+        .and_then(|stored| {
+            let expires_at = match stored.expires_at {
+                Some(secs) => Some(
+                    UNIX_EPOCH
+                        .checked_add(Duration::from_secs(secs))
+                        .ok_or_else(|| anyhow!(format!("Invalid expiry {}", secs)))?,
+                ),
+                None => None,
+            };
+            let value = base64::decode(&stored.value).context("Decoding KV entry")?;
+            Ok((stored.namespace, stored.key, Entry { value, expires_at }))
+        });
+    decoded
+        .inspect_err(|err| tracing::warn!(?err, "Ignoring invalid KV entry"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::kv::configmap::{decode_entry, encode_entry, encode_key};
+    use crate::app::wasm::local::kv::Entry;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_encoding() {
+        let long_key = "k".repeat(512);
+        let key = encode_key("workflow/hello-abc12", &long_key);
+        assert!(key.len() <= 253);
+        assert!(key.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(key, encode_key("workflow/hello-abc12", "a/b c"));
+
+        let entry = Entry {
+            value: b"value".to_vec(),
+            expires_at: Some(UNIX_EPOCH + Duration::from_secs(1700000000)),
+        };
+        let encoded = encode_entry("workflow/hello-abc12", &long_key, &entry).unwrap();
+        assert_eq!(
+            Some(("workflow/hello-abc12".to_string(), long_key, entry)),
+            decode_entry(&encoded)
+        );
+        assert_eq!(None, decode_entry("not json"));
+        assert_eq!(
+            None,
+            decode_entry(
+                r#"{"namespace":"global","key":"a","value":"","expires_at":18446744073709551615}"#
+            )
+        );
+    }
+}
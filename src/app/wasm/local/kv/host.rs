@@ -0,0 +1,310 @@
+//! Host functions of the key-value store. Components import the `kv` interface of
+//! `crates/workflow-model/wit/workflow-plugin.wit`, modules import the functions of the module
+//! `workflow_kv`, which `workflow_model::plugin::kv` wraps.
+
+use crate::app::wasm::local::guest_memory;
+use crate::app::wasm::local::kv::{KVCtx, KVError, Scope};
+use crate::app::wasm::local::wit::kv as bindings;
+use async_trait::async_trait;
+use std::time::Duration;
+use wasmtime::{Caller, Linker, Memory};
+
+/// Module that modules import the host functions from
+const MODULE: &str = "workflow_kv";
+
+/// Error codes of the host functions of modules, 0 means success
+#[derive(Debug)]
+enum ErrorCode {
+    NotFound,
+    BufferTooSmall,
+    MemoryNotFound,
+    MemoryAccessError,
+    Utf8Error,
+    InvalidScope,
+    NotAllowed,
+    InvalidKey,
+    ValueTooLarge,
+    BackendError,
+    InvalidTtl,
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::NotFound => 1,
+            ErrorCode::BufferTooSmall => 2,
+            ErrorCode::MemoryNotFound => 3,
+            ErrorCode::MemoryAccessError => 4,
+            ErrorCode::Utf8Error => 5,
+            ErrorCode::InvalidScope => 6,
+            ErrorCode::NotAllowed => 7,
+            ErrorCode::InvalidKey => 8,
+            ErrorCode::ValueTooLarge => 9,
+            ErrorCode::BackendError => 10,
+            ErrorCode::InvalidTtl => 11,
+        }
+    }
+}
+
+impl From<KVError> for ErrorCode {
+    fn from(err: KVError) -> Self {
+        match err {
+            KVError::NotAllowed => ErrorCode::NotAllowed,
+            KVError::InvalidKey => ErrorCode::InvalidKey,
+            KVError::InvalidTtl => ErrorCode::InvalidTtl,
+            KVError::ValueTooLarge(_) => ErrorCode::ValueTooLarge,
+            KVError::Backend(_) => ErrorCode::BackendError,
+        }
+    }
+}
+
+fn error_code(result: Result<(), ErrorCode>) -> u32 {
+    match result {
+        Ok(_) => 0,
+        Err(code) => code.into(),
+    }
+}
+
+/// Defines the host functions of the KVStore for modules
+pub fn add_to_linker<T: Send + 'static>(
+    linker: &mut Linker<T>,
+    get: impl Fn(&mut T) -> &mut KVCtx + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.func_wrap_async(
+        MODULE,
+        "get",
+        move |mut caller: Caller<'_, T>,
+              (scope, key_ptr, key_len, buf_ptr, buf_len, written_ptr): BufferParams| {
+            Box::new(async move {
+                let buffer = Buffer {
+                    ptr: buf_ptr,
+                    len: buf_len,
+                    written_ptr,
+                };
+                error_code(kv_get(&mut caller, get, scope, (key_ptr, key_len), buffer).await)
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        MODULE,
+        "set",
+        move |mut caller: Caller<'_, T>,
+              (scope, key_ptr, key_len, value_ptr, value_len, ttl_seconds): SetParams| {
+            Box::new(async move {
+                let ttl = Some(Duration::from_secs(ttl_seconds)).filter(|ttl| !ttl.is_zero());
+                let key = (key_ptr, key_len);
+                let value = (value_ptr, value_len);
+                error_code(kv_set(&mut caller, get, scope, key, value, ttl).await)
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        MODULE,
+        "delete",
+        move |mut caller: Caller<'_, T>, (scope, key_ptr, key_len): (u32, u32, u32)| {
+            Box::new(async move {
+                error_code(kv_delete(&mut caller, get, scope, (key_ptr, key_len)).await)
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        MODULE,
+        "list",
+        move |mut caller: Caller<'_, T>,
+              (scope, prefix_ptr, prefix_len, buf_ptr, buf_len, written_ptr): BufferParams| {
+            Box::new(async move {
+                let buffer = Buffer {
+                    ptr: buf_ptr,
+                    len: buf_len,
+                    written_ptr,
+                };
+                let prefix = (prefix_ptr, prefix_len);
+                error_code(kv_list(&mut caller, get, scope, prefix, buffer).await)
+            })
+        },
+    )?;
+    Ok(())
+}
+
+type BufferParams = (u32, u32, u32, u32, u32, u32);
+type SetParams = (u32, u32, u32, u32, u32, u64);
+
+/// Buffer of the module that a host function writes to
+struct Buffer {
+    ptr: u32,
+    len: u32,
+    written_ptr: u32,
+}
+
+fn scope(scope: u32) -> Result<Scope, ErrorCode> {
+    match scope {
+        0 => Ok(Scope::Workflow),
+        1 => Ok(Scope::Global),
+        _ => Err(ErrorCode::InvalidScope),
+    }
+}
+
+async fn kv_get<T: Send>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut KVCtx,
+    scope_code: u32,
+    key: (u32, u32),
+    buffer: Buffer,
+) -> Result<(), ErrorCode> {
+    let scope = scope(scope_code)?;
+    let memory = memory(caller)?;
+    let key = read_string(caller, memory, key.0, key.1)?;
+    // The KVCtx is cloned, so that the Caller is not borrowed while the KVStore is awaited
+    let kv = get(caller.data_mut()).clone();
+    let value = kv.get(scope, &key).await?.ok_or(ErrorCode::NotFound)?;
+    write_buffer(caller, memory, &value, buffer)
+}
+
+async fn kv_set<T: Send>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut KVCtx,
+    scope_code: u32,
+    key: (u32, u32),
+    value: (u32, u32),
+    ttl: Option<Duration>,
+) -> Result<(), ErrorCode> {
+    let scope = scope(scope_code)?;
+    let memory = memory(caller)?;
+    let key = read_string(caller, memory, key.0, key.1)?;
+    let value = read_bytes(caller, memory, value.0, value.1)?;
+    let kv = get(caller.data_mut()).clone();
+    Ok(kv.set(scope, &key, value, ttl).await?)
+}
+
+async fn kv_delete<T: Send>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut KVCtx,
+    scope_code: u32,
+    key: (u32, u32),
+) -> Result<(), ErrorCode> {
+    let scope = scope(scope_code)?;
+    let memory = memory(caller)?;
+    let key = read_string(caller, memory, key.0, key.1)?;
+    let kv = get(caller.data_mut()).clone();
+    Ok(kv.delete(scope, &key).await?)
+}
+
+/// Writes the keys as lines to the buffer
+async fn kv_list<T: Send>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut KVCtx,
+    scope_code: u32,
+    prefix: (u32, u32),
+    buffer: Buffer,
+) -> Result<(), ErrorCode> {
+    let scope = scope(scope_code)?;
+    let memory = memory(caller)?;
+    let prefix = read_string(caller, memory, prefix.0, prefix.1)?;
+    let kv = get(caller.data_mut()).clone();
+    let keys = kv.list(scope, &prefix).await?.join("\n");
+    write_buffer(caller, memory, keys.as_bytes(), buffer)
+}
+
+fn memory<T>(caller: &mut Caller<'_, T>) -> Result<Memory, ErrorCode> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or(ErrorCode::MemoryNotFound)
+}
+
+fn read_bytes<T>(
+    caller: &mut Caller<'_, T>,
+    memory: Memory,
+    ptr: u32,
+    len: u32,
+) -> Result<Vec<u8>, ErrorCode> {
+    guest_memory::read(&memory, caller, ptr, len).ok_or(ErrorCode::MemoryAccessError)
+}
+
+fn read_string<T>(
+    caller: &mut Caller<'_, T>,
+    memory: Memory,
+    ptr: u32,
+    len: u32,
+) -> Result<String, ErrorCode> {
+    String::from_utf8(read_bytes(caller, memory, ptr, len)?).map_err(|_| ErrorCode::Utf8Error)
+}
+
+/// Writes data and its length to the buffer of the module. If the buffer is too small, only the
+/// length is written, so that the module can retry with a larger buffer.
+fn write_buffer<T>(
+    caller: &mut Caller<'_, T>,
+    memory: Memory,
+    data: &[u8],
+    buffer: Buffer,
+) -> Result<(), ErrorCode> {
+    let len = (data.len() as u32).to_le_bytes();
+    memory
+        .write(&mut *caller, buffer.written_ptr as usize, &len)
+        .map_err(|_| ErrorCode::MemoryAccessError)?;
+    if data.len() > buffer.len as usize {
+        return Err(ErrorCode::BufferTooSmall);
+    }
+    memory
+        .write(caller, buffer.ptr as usize, data)
+        .map_err(|_| ErrorCode::MemoryAccessError)
+}
+
+impl From<bindings::Scope> for Scope {
+    fn from(scope: bindings::Scope) -> Self {
+        match scope {
+            bindings::Scope::Workflow => Scope::Workflow,
+            bindings::Scope::Global => Scope::Global,
+        }
+    }
+}
+
+impl From<KVError> for bindings::KvError {
+    fn from(err: KVError) -> Self {
+        match err {
+            KVError::NotAllowed => bindings::KvError::NotAllowed,
+            KVError::InvalidKey => bindings::KvError::InvalidKey,
+            KVError::InvalidTtl => bindings::KvError::InvalidTtl,
+            KVError::ValueTooLarge(_) => bindings::KvError::ValueTooLarge,
+            KVError::Backend(err) => bindings::KvError::Backend(err),
+        }
+    }
+}
+
+#[async_trait]
+impl bindings::Host for KVCtx {
+    async fn get(
+        &mut self,
+        scope: bindings::Scope,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, bindings::KvError> {
+        Ok(KVCtx::get(self, scope.into(), &key).await?)
+    }
+
+    async fn set(
+        &mut self,
+        scope: bindings::Scope,
+        key: String,
+        value: Vec<u8>,
+        ttl_seconds: Option<u64>,
+    ) -> Result<(), bindings::KvError> {
+        let ttl = ttl_seconds.map(Duration::from_secs);
+        Ok(KVCtx::set(self, scope.into(), &key, value, ttl).await?)
+    }
+
+    async fn delete(
+        &mut self,
+        scope: bindings::Scope,
+        key: String,
+    ) -> Result<(), bindings::KvError> {
+        Ok(KVCtx::delete(self, scope.into(), &key).await?)
+    }
+
+    async fn list(
+        &mut self,
+        scope: bindings::Scope,
+        prefix: String,
+    ) -> Result<Vec<String>, bindings::KvError> {
+        Ok(KVCtx::list(self, scope.into(), &prefix).await?)
+    }
+}
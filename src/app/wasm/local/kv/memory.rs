@@ -0,0 +1,258 @@
+use crate::app::wasm::local::kv::{Entry, KVStore};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// MemoryLimits bound the entries of all scopes kept in memory
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryLimits {
+    pub max_entries: usize,
+    /// Maximum size of the namespaces, keys and values of all entries in bytes
+    pub max_size: u64,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        MemoryLimits {
+            max_entries: 10000,
+            max_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Number of sets after which the expired entries are removed
+const SWEEP_INTERVAL: usize = 1024;
+
+/// MemoryKVStore keeps the entries in the plugin process, so they are lost when it restarts.
+/// Expired entries are removed when they are read, every SWEEP_INTERVAL sets, and before a set
+/// would exceed the MemoryLimits. Setting an entry fails once the store would exceed its
+/// MemoryLimits.
+#[derive(Default)]
+pub struct MemoryKVStore {
+    limits: MemoryLimits,
+    entries: Mutex<Entries>,
+}
+
+impl MemoryKVStore {
+    pub fn new(limits: MemoryLimits) -> Self {
+        MemoryKVStore {
+            limits,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+}
+
+/// Entries keeps the total size of its entries, so that the limits are checked without a scan
+#[derive(Default)]
+struct Entries {
+    by_key: BTreeMap<(String, String), Entry>,
+    size: u64,
+    sets_since_sweep: usize,
+    /// Time at or before which the first entry expires, so the entries are only swept if some of
+    /// them expired
+    next_expiry: Option<SystemTime>,
+}
+
+impl Entries {
+    fn insert(&mut self, key: (String, String), entry: Entry) {
+        self.size += entry_size(&key, &entry);
+        self.next_expiry = self.next_expiry.into_iter().chain(entry.expires_at).min();
+        if let Some(previous) = self.by_key.insert(key.clone(), entry) {
+            self.size -= entry_size(&key, &previous);
+        }
+    }
+
+    fn remove(&mut self, key: &(String, String)) -> Option<Entry> {
+        let entry = self.by_key.remove(key)?;
+        self.size -= entry_size(key, &entry);
+        Some(entry)
+    }
+
+    fn has_expired(&self, now: SystemTime) -> bool {
+        matches!(self.next_expiry, Some(next_expiry) if next_expiry <= now)
+    }
+
+    fn sweep(&mut self, now: SystemTime) {
+        let mut size = self.size;
+        self.by_key.retain(|key, entry| {
+            let expired = entry.expired(now);
+            if expired {
+                size -= entry_size(key, entry);
+            }
+            !expired
+        });
+        self.size = size;
+        self.sets_since_sweep = 0;
+        self.next_expiry = self
+            .by_key
+            .values()
+            .filter_map(|entry| entry.expires_at)
+            .min();
+    }
+
+    /// Returns the number of entries and their size once the entry replaced the one with the same
+    /// key, if any
+    fn usage_with(&self, key: &(String, String), entry: &Entry) -> (usize, u64) {
+        match self.by_key.get(key) {
+            Some(previous) => (
+                self.by_key.len(),
+                self.size - entry_size(key, previous) + entry_size(key, entry),
+            ),
+            None => (self.by_key.len() + 1, self.size + entry_size(key, entry)),
+        }
+    }
+}
+
+fn entry_size((namespace, key): &(String, String), entry: &Entry) -> u64 {
+    (namespace.len() + key.len() + entry.value.len()) as u64
+}
+
+#[async_trait]
+impl KVStore for MemoryKVStore {
+    async fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (namespace.to_owned(), key.to_owned());
+        match entries.by_key.get(&key) {
+            Some(entry) if entry.expired(SystemTime::now()) => {
+                entries.remove(&key);
+                Ok(None)
+            }
+            entry => Ok(entry.map(|entry| entry.value.to_owned())),
+        }
+    }
+
+    async fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    ) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (namespace.to_owned(), key.to_owned());
+        let entry = Entry { value, expires_at };
+        let now = SystemTime::now();
+        entries.sets_since_sweep += 1;
+        let (count, size) = entries.usage_with(&key, &entry);
+        let exceeded = count > self.limits.max_entries || size > self.limits.max_size;
+        if entries.has_expired(now) && (exceeded || entries.sets_since_sweep >= SWEEP_INTERVAL) {
+            entries.sweep(now);
+        }
+        let (count, size) = entries.usage_with(&key, &entry);
+        if count > self.limits.max_entries {
+            return Err(anyhow!(format!(
+                "KV store exceeds the limit of {} entries",
+                self.limits.max_entries
+            )));
+        }
+        if size > self.limits.max_size {
+            return Err(anyhow!(format!(
+                "KV store exceeds the limit of {} bytes",
+                self.limits.max_size
+            )));
+        }
+        entries.insert(key, entry);
+        Ok(())
+    }
+
+    async fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&(namespace.to_owned(), key.to_owned()));
+        Ok(())
+    }
+
+    async fn list(&self, namespace: &str, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let entries = self.entries.lock().unwrap();
+        let now = SystemTime::now();
+        Ok(entries
+            .by_key
+            .range((namespace.to_owned(), prefix.to_owned())..)
+            .take_while(|((ns, key), _)| ns == namespace && key.starts_with(prefix))
+            .filter(|(_, entry)| !entry.expired(now))
+            .map(|((_, key), _)| key.to_owned())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::kv::memory::{MemoryKVStore, MemoryLimits};
+    use crate::app::wasm::local::kv::KVStore;
+    use futures::executor::block_on;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_memory_kv_store() {
+        let store = MemoryKVStore::default();
+        block_on(store.set("workflow/a", "b", b"1".to_vec(), None)).unwrap();
+        block_on(store.set("workflow/a", "a/1", b"2".to_vec(), None)).unwrap();
+        block_on(store.set("workflow/a", "a/2", b"3".to_vec(), None)).unwrap();
+        block_on(store.set("workflow/ab", "a/3", b"4".to_vec(), None)).unwrap();
+        block_on(store.set("workflow/a", "a/4", b"5".to_vec(), Some(SystemTime::now()))).unwrap();
+
+        assert_eq!(
+            Some(b"1".to_vec()),
+            block_on(store.get("workflow/a", "b")).unwrap()
+        );
+        assert_eq!(None, block_on(store.get("workflow/a", "a/4")).unwrap());
+        assert_eq!(
+            vec!["a/1", "a/2"],
+            block_on(store.list("workflow/a", "a/")).unwrap()
+        );
+        assert_eq!(
+            vec!["a/1", "a/2", "b"],
+            block_on(store.list("workflow/a", "")).unwrap()
+        );
+
+        block_on(store.delete("workflow/a", "a/1")).unwrap();
+        assert_eq!(None, block_on(store.get("workflow/a", "a/1")).unwrap());
+    }
+
+    #[test]
+    fn test_limits() {
+        let store = MemoryKVStore::new(MemoryLimits {
+            max_entries: 2,
+            max_size: 16,
+        });
+        block_on(store.set("global", "a", b"1".to_vec(), None)).unwrap();
+        block_on(store.set("global", "b", b"1".to_vec(), None)).unwrap();
+        assert!(block_on(store.set("global", "c", b"1".to_vec(), None)).is_err());
+        // Replacing an entry does not add one
+        block_on(store.set("global", "b", b"2".to_vec(), None)).unwrap();
+        assert!(block_on(store.set("global", "b", b"too large".to_vec(), None)).is_err());
+        assert_eq!(
+            Some(b"2".to_vec()),
+            block_on(store.get("global", "b")).unwrap()
+        );
+
+        block_on(store.delete("global", "a")).unwrap();
+        block_on(store.set("global", "c", b"1".to_vec(), None)).unwrap();
+    }
+
+    #[test]
+    fn test_expired_entries_free_limits() {
+        let store = MemoryKVStore::new(MemoryLimits {
+            max_entries: 2,
+            max_size: 16,
+        });
+        block_on(store.set("global", "a", b"1".to_vec(), Some(SystemTime::now()))).unwrap();
+        block_on(store.set("global", "b", b"1".to_vec(), None)).unwrap();
+        block_on(store.set("global", "c", b"1".to_vec(), None)).unwrap();
+        assert_eq!(None, block_on(store.get("global", "a")).unwrap());
+        assert!(block_on(store.set("global", "d", b"1".to_vec(), None)).is_err());
+
+        // Each entry takes 8 bytes
+        let store = MemoryKVStore::new(MemoryLimits {
+            max_entries: 10,
+            max_size: 16,
+        });
+        block_on(store.set("global", "a", b"1".to_vec(), Some(SystemTime::now()))).unwrap();
+        block_on(store.set("global", "b", b"1".to_vec(), None)).unwrap();
+        block_on(store.set("global", "c", b"1".to_vec(), None)).unwrap();
+        assert!(block_on(store.set("global", "d", b"1".to_vec(), None)).is_err());
+        block_on(store.delete("global", "c")).unwrap();
+        block_on(store.set("global", "d", b"1".to_vec(), None)).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! Key-value store that modules use to keep state between the steps of a workflow. Keys are
+//! scoped per workflow, and optionally in a global scope shared by all workflows.
+
+use crate::app::model::KVPermissions;
+use crate::app::wasm::local::kv::configmap::ConfigMapKVStore;
+use crate::app::wasm::local::kv::memory::MemoryKVStore;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+mod configmap;
+pub mod host;
+mod memory;
+
+pub use memory::MemoryLimits;
+
+/// Maximum length of a key in bytes
+const MAX_KEY_SIZE: usize = 512;
+
+/// KVStore persists the entries of all scopes. Each scope is a namespace of keys.
+#[async_trait]
+pub trait KVStore {
+    async fn get(&self, namespace: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    async fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    ) -> anyhow::Result<()>;
+    async fn delete(&self, namespace: &str, key: &str) -> anyhow::Result<()>;
+    /// Returns the keys of the namespace that start with prefix, in lexicographic order
+    async fn list(&self, namespace: &str, prefix: &str) -> anyhow::Result<Vec<String>>;
+}
+
+pub type DynKVStore = Arc<dyn KVStore + Send + Sync>;
+
+/// Creates the KVStore shared by all requests. It keeps the entries in memory, unless a ConfigMap
+/// is given that stores them across restarts and replicas of the plugin.
+pub(crate) fn create_kv_store(
+    kv_config_map: &Option<String>,
+    client: &Option<kube::Client>,
+    namespace: Option<&str>,
+    memory_limits: MemoryLimits,
+) -> anyhow::Result<DynKVStore> {
+    match kv_config_map {
+        Some(name) => match client {
+            Some(client) => Ok(Arc::new(ConfigMapKVStore::new(
+                client.clone(),
+                namespace,
+                name,
+            ))),
+            None => Err(anyhow!(
+                "Kube client is required for the ConfigMap KV store"
+            )),
+        },
+        None => Ok(Arc::new(MemoryKVStore::new(memory_limits))),
+    }
+}
+
+/// Entry is a value with an optional expiry
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<SystemTime>,
+}
+
+impl Entry {
+    fn expired(&self, now: SystemTime) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Workflow,
+    Global,
+}
+
+/// KVError is returned to the module when it cannot access an entry
+#[derive(Debug, PartialEq, Eq)]
+pub enum KVError {
+    NotAllowed,
+    InvalidKey,
+    InvalidTtl,
+    ValueTooLarge(u64),
+    Backend(String),
+}
+
+impl Display for KVError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KVError::NotAllowed => write!(f, "Module is not allowed to access the scope"),
+            KVError::InvalidKey => write!(f, "Key is empty, too long or contains a newline"),
+            KVError::InvalidTtl => write!(f, "TTL exceeds the time the plugin can represent"),
+            KVError::ValueTooLarge(size) => {
+                write!(f, "Value exceeds the limit of {} bytes", size)
+            }
+            KVError::Backend(err) => write!(f, "KV store failed: {}", err),
+        }
+    }
+}
+
+impl KVError {
+    fn backend(err: anyhow::Error) -> Self {
+        tracing::error!(?err, "KV store failed");
+        KVError::Backend(format!("{:#}", err))
+    }
+}
+
+/// KVCtx is the access of one module to the KVStore. Without KVPermissions, the module cannot
+/// access any scope.
+#[derive(Clone, Default)]
+pub struct KVCtx {
+    store: Option<DynKVStore>,
+    workflow_name: String,
+    global: bool,
+    max_value_size: Option<u64>,
+}
+
+impl KVCtx {
+    pub fn new(store: DynKVStore, workflow_name: &str, permissions: &KVPermissions) -> Self {
+        KVCtx {
+            store: Some(store),
+            workflow_name: workflow_name.to_owned(),
+            global: permissions.global,
+            max_value_size: permissions.max_value_size.map(|size| size.bytes()),
+        }
+    }
+
+    pub async fn get(&self, scope: Scope, key: &str) -> Result<Option<Vec<u8>>, KVError> {
+        let (store, namespace) = self.resolve(scope)?;
+        check_key(key)?;
+        tracing::debug!(target: "wasm_kv", %namespace, key, "KV get");
+        store.get(&namespace, key).await.map_err(KVError::backend)
+    }
+
+    pub async fn set(
+        &self,
+        scope: Scope,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), KVError> {
+        let (store, namespace) = self.resolve(scope)?;
+        check_key(key)?;
+        if let Some(max_size) = self.max_value_size {
+            if value.len() as u64 > max_size {
+                return Err(KVError::ValueTooLarge(max_size));
+            }
+        }
+        // The TTL is chosen by the module, so it may be too large to add to the current time
+        let expires_at = ttl
+            .map(|ttl| {
+                SystemTime::now()
+                    .checked_add(ttl)
+                    .ok_or(KVError::InvalidTtl)
+            })
+            .transpose()?;
+        tracing::debug!(target: "wasm_kv", %namespace, key, size = value.len(), ?ttl, "KV set");
+        store
+            .set(&namespace, key, value, expires_at)
+            .await
+            .map_err(KVError::backend)
+    }
+
+    pub async fn delete(&self, scope: Scope, key: &str) -> Result<(), KVError> {
+        let (store, namespace) = self.resolve(scope)?;
+        check_key(key)?;
+        tracing::debug!(target: "wasm_kv", %namespace, key, "KV delete");
+        store
+            .delete(&namespace, key)
+            .await
+            .map_err(KVError::backend)
+    }
+
+    pub async fn list(&self, scope: Scope, prefix: &str) -> Result<Vec<String>, KVError> {
+        let (store, namespace) = self.resolve(scope)?;
+        tracing::debug!(target: "wasm_kv", %namespace, prefix, "KV list");
+        store
+            .list(&namespace, prefix)
+            .await
+            .map_err(KVError::backend)
+    }
+
+    /// Returns the store and the namespace of scope, if the module may access it
+    fn resolve(&self, scope: Scope) -> Result<(&DynKVStore, String), KVError> {
+        let store = self.store.as_ref().ok_or(KVError::NotAllowed)?;
+        match scope {
+            Scope::Workflow => Ok((store, format!("workflow/{}", self.workflow_name))),
+            Scope::Global if self.global => Ok((store, "global".to_string())),
+            Scope::Global => {
+                tracing::warn!("Module is not allowed to access the global KV scope");
+                Err(KVError::NotAllowed)
+            }
+        }
+    }
+}
+
+/// Keys are listed separated by newlines, so they must not contain one
+fn check_key(key: &str) -> Result<(), KVError> {
+    if key.is_empty() || key.len() > MAX_KEY_SIZE || key.contains('\n') {
+        return Err(KVError::InvalidKey);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::quantity::Quantity;
+    use crate::app::model::KVPermissions;
+    use crate::app::wasm::local::kv::memory::MemoryKVStore;
+    use crate::app::wasm::local::kv::{DynKVStore, KVCtx, KVError, Scope};
+    use futures::executor::block_on;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_scopes() {
+        let store: DynKVStore = Arc::new(MemoryKVStore::default());
+        let permissions = KVPermissions {
+            global: false,
+            max_value_size: Some(Quantity(4)),
+        };
+        let first = KVCtx::new(store.clone(), "workflow-a", &permissions);
+        let second = KVCtx::new(store.clone(), "workflow-b", &permissions);
+
+        block_on(first.set(Scope::Workflow, "count", b"1".to_vec(), None)).unwrap();
+        assert_eq!(
+            Ok(Some(b"1".to_vec())),
+            block_on(first.get(Scope::Workflow, "count"))
+        );
+        assert_eq!(Ok(None), block_on(second.get(Scope::Workflow, "count")));
+        assert_eq!(
+            Err(KVError::NotAllowed),
+            block_on(first.get(Scope::Global, "count"))
+        );
+        assert_eq!(
+            Err(KVError::ValueTooLarge(4)),
+            block_on(first.set(Scope::Workflow, "count", b"12345".to_vec(), None))
+        );
+        assert_eq!(
+            Err(KVError::InvalidKey),
+            block_on(first.get(Scope::Workflow, "a\nb"))
+        );
+
+        let global = KVCtx::new(
+            store,
+            "workflow-b",
+            &KVPermissions {
+                global: true,
+                max_value_size: None,
+            },
+        );
+        block_on(global.set(Scope::Global, "count", b"2".to_vec(), None)).unwrap();
+        assert_eq!(
+            Ok(Some(b"1".to_vec())),
+            block_on(first.get(Scope::Workflow, "count"))
+        );
+
+        assert_eq!(
+            Err(KVError::InvalidTtl),
+            block_on(first.set(Scope::Workflow, "count", b"1".to_vec(), Some(Duration::MAX)))
+        );
+
+        let denied = KVCtx::default();
+        assert_eq!(
+            Err(KVError::NotAllowed),
+            block_on(denied.get(Scope::Workflow, "count"))
+        );
+    }
+}
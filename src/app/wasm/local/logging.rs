@@ -0,0 +1,270 @@
+//! Structured logging of modules. Components import the `log` interface of
+//! `crates/workflow-model/wit/workflow-plugin.wit`, modules import the function `log` of the
+//! module `workflow_log`, which `workflow_model::plugin::log!` wraps.
+
+use crate::app::wasm::local::guest_memory;
+use crate::app::wasm::local::secrets::Secrets;
+use crate::app::wasm::local::wit::log as bindings;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use wasmtime::{Caller, Linker};
+
+/// Module that modules import the host function from
+const MODULE: &str = "workflow_log";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// LogRecord is a record of the log artifact
+#[derive(Debug, Serialize)]
+pub struct LogRecord {
+    pub timestamp: String,
+    pub level: Level,
+    pub message: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// LogCtx emits the log records of one module as tracing events, and collects them for the log
+/// artifact if enabled
+#[derive(Default)]
+pub struct LogCtx {
+    workflow_name: String,
+    module_name: String,
+    secrets: Secrets,
+    /// JSON lines of the collected records, None if the records are not collected
+    collected: Option<String>,
+    max_size: usize,
+    truncated: bool,
+}
+
+impl LogCtx {
+    pub fn new(workflow_name: &str, module_name: &str, secrets: Secrets) -> Self {
+        LogCtx {
+            workflow_name: workflow_name.to_owned(),
+            module_name: module_name.to_owned(),
+            secrets,
+            ..Default::default()
+        }
+    }
+
+    /// Collects up to max_size bytes of records for the log artifact
+    pub fn collect(&mut self, max_size: usize) {
+        self.collected = Some(String::new());
+        self.max_size = max_size;
+    }
+
+    /// Returns the collected records as JSON lines
+    pub fn take_records(&mut self) -> String {
+        self.collected.take().unwrap_or_default()
+    }
+
+    pub fn log(&mut self, level: Level, message: &str, fields: Vec<(String, String)>) {
+        let record = LogRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            level,
+            message: self.secrets.redact(message),
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key, self.secrets.redact(&value)))
+                .collect(),
+        };
+        self.trace(&record);
+        if let Some(collected) = self.collected.as_mut() {
+            if self.truncated {
+                return;
+            }
+            let line = serde_json::to_string(&record).unwrap_or_default();
+            if collected.len() + line.len() + 1 > self.max_size {
+                self.truncated = true;
+                collected.push_str("[truncated]\n");
+                return;
+            }
+            collected.push_str(&line);
+            collected.push('\n');
+        }
+    }
+
+    /// Emits the record within the span of the module execution, so it becomes part of the trace
+    /// of the request
+    fn trace(&self, record: &LogRecord) {
+        let fields = record
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{}={:?}", key, value))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let (workflow, module) = (&self.workflow_name, &self.module_name);
+        macro_rules! emit {
+            ($level:expr) => {
+                tracing::event!(
+                    target: "wasm_module",
+                    $level,
+                    workflow = %workflow,
+                    module = %module,
+                    fields = %fields,
+                    "{}",
+                    record.message
+                )
+            };
+        }
+        match record.level {
+            Level::Trace => emit!(tracing::Level::TRACE),
+            Level::Debug => emit!(tracing::Level::DEBUG),
+            Level::Info => emit!(tracing::Level::INFO),
+            Level::Warn => emit!(tracing::Level::WARN),
+            Level::Error => emit!(tracing::Level::ERROR),
+        }
+    }
+}
+
+/// Error codes of the host function of modules, 0 means success
+#[derive(Debug)]
+enum ErrorCode {
+    MemoryNotFound,
+    MemoryAccessError,
+    Utf8Error,
+    InvalidLevel,
+    InvalidEncoding,
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::MemoryNotFound => 1,
+            ErrorCode::MemoryAccessError => 2,
+            ErrorCode::Utf8Error => 3,
+            ErrorCode::InvalidLevel => 4,
+            ErrorCode::InvalidEncoding => 5,
+        }
+    }
+}
+
+/// Defines the host function of the structured logs for modules
+pub fn add_to_linker<T: Send + 'static>(
+    linker: &mut Linker<T>,
+    get: impl Fn(&mut T) -> &mut LogCtx + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.func_wrap(
+        MODULE,
+        "log",
+        move |mut caller: Caller<'_, T>,
+              level: u32,
+              message_ptr: u32,
+              message_len: u32,
+              fields_ptr: u32,
+              fields_len: u32| {
+            let message = (message_ptr, message_len);
+            let fields = (fields_ptr, fields_len);
+            match log(&mut caller, get, level, message, fields) {
+                Ok(_) => 0,
+                Err(code) => code.into(),
+            }
+        },
+    )?;
+    Ok(())
+}
+
+fn log<T>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut LogCtx,
+    level: u32,
+    message: (u32, u32),
+    fields: (u32, u32),
+) -> Result<(), ErrorCode> {
+    let level = match level {
+        0 => Level::Trace,
+        1 => Level::Debug,
+        2 => Level::Info,
+        3 => Level::Warn,
+        4 => Level::Error,
+        _ => return Err(ErrorCode::InvalidLevel),
+    };
+    let message = read_string(caller, message)?;
+    let fields = parse_fields(&read_string(caller, fields)?)?;
+    get(caller.data_mut()).log(level, &message, fields);
+    Ok(())
+}
+
+fn read_string<T>(caller: &mut Caller<'_, T>, (ptr, len): (u32, u32)) -> Result<String, ErrorCode> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or(ErrorCode::MemoryNotFound)?;
+    let buf = guest_memory::read(&memory, caller, ptr, len).ok_or(ErrorCode::MemoryAccessError)?;
+    String::from_utf8(buf).map_err(|_| ErrorCode::Utf8Error)
+}
+
+/// Fields are passed by modules as lines of `key=value`
+fn parse_fields(s: &str) -> Result<Vec<(String, String)>, ErrorCode> {
+    s.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (key, value) = line.split_once('=').ok_or(ErrorCode::InvalidEncoding)?;
+            Ok((key.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+impl From<bindings::Level> for Level {
+    fn from(level: bindings::Level) -> Self {
+        match level {
+            bindings::Level::Trace => Level::Trace,
+            bindings::Level::Debug => Level::Debug,
+            bindings::Level::Info => Level::Info,
+            bindings::Level::Warn => Level::Warn,
+            bindings::Level::Error => Level::Error,
+        }
+    }
+}
+
+#[async_trait]
+impl bindings::Host for LogCtx {
+    async fn log(&mut self, level: bindings::Level, message: String, fields: Vec<bindings::Field>) {
+        let fields = fields
+            .into_iter()
+            .map(|field| (field.key, field.value))
+            .collect();
+        LogCtx::log(self, level.into(), &message, fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::logging::{parse_fields, Level, LogCtx};
+    use crate::app::wasm::local::secrets::redacting_secrets;
+
+    #[test]
+    fn test_log() {
+        let mut log = LogCtx::new(
+            "hello-abc12",
+            "ghcr.io/my-org/my-module@sha256:0",
+            redacting_secrets(),
+        );
+        log.collect(200);
+
+        let fields = parse_fields("width=640\nheight=480\ntoken=s3cr3t\n").unwrap();
+        log.log(Level::Info, "Resized image", fields);
+        log.log(Level::Warn, &"x".repeat(100), Vec::new());
+        log.log(Level::Info, "Dropped", Vec::new());
+
+        let records = log.take_records();
+        let lines: Vec<&str> = records.lines().collect();
+        assert_eq!(2, lines.len());
+        let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!("info", record["level"]);
+        assert_eq!("Resized image", record["message"]);
+        assert_eq!("640", record["fields"]["width"]);
+        assert_eq!("[REDACTED]", record["fields"]["token"]);
+        assert_eq!("[truncated]", lines[1]);
+
+        assert!(parse_fields("no separator").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+/// Lru keeps up to capacity entries in memory and evicts the least recently used entry when
+/// another one is inserted. A capacity of 0 disables it.
+pub struct Lru<V> {
+    capacity: usize,
+    entries: HashMap<String, LruEntry<V>>,
+    clock: u64,
+}
+
+struct LruEntry<V> {
+    value: V,
+    last_used: u64,
+}
+
+impl<V: Clone> Lru<V> {
+    pub fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            entry.value.clone()
+        })
+    }
+
+    pub fn put(&mut self, key: &str, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(key) && self.entries.len() >= self.capacity {
+            let least_recently_used = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.to_owned());
+            if let Some(evicted) = least_recently_used {
+                tracing::trace!(%evicted, "Evicting module from memory");
+                self.entries.remove(&evicted);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key.to_owned(),
+            LruEntry {
+                value,
+                last_used: self.clock,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::lru::Lru;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut lru: Lru<u32> = Lru::new(2);
+        lru.put("a", 1);
+        lru.put("b", 2);
+        assert_eq!(Some(1), lru.get("a"));
+        lru.put("c", 3);
+        assert_eq!(None, lru.get("b"));
+        assert_eq!(Some(1), lru.get("a"));
+        assert_eq!(Some(3), lru.get("c"));
+
+        lru.put("c", 4);
+        assert_eq!(Some(4), lru.get("c"));
+        assert_eq!(Some(1), lru.get("a"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut lru: Lru<u32> = Lru::new(0);
+        lru.put("a", 1);
+        assert_eq!(None, lru.get("a"));
+    }
+}
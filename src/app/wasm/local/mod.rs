@@ -1,30 +1,662 @@
-use crate::app::model::ModulePermissions;
-use crate::app::wasm::local::cache::ModuleCache;
-use crate::app::wasm::local::interface::{WASIModule, WorkflowPlugin};
-use crate::app::wasm::{Runner, WasmError};
-use anyhow::anyhow;
+use crate::app::k8s;
+use crate::app::model::registry::RegistryCredentials;
+use crate::app::model::signature::SignatureMode;
+use crate::app::model::{EnvVarSource, ImagePullSecret, ModuleSource, WasmPluginConfig};
+use crate::app::wasm::local::cache::DynModuleCache;
+use crate::app::wasm::local::http::policy::{HttpPolicy, InjectedHeader};
+use crate::app::wasm::local::interface::{
+    Compiled, LimitExceeded, Linked, Linkers, ModuleEnvironment, ModuleInstance,
+};
+use crate::app::wasm::local::kv::{DynKVStore, KVCtx};
+use crate::app::wasm::local::logging::LogCtx;
+use crate::app::wasm::local::lru::Lru;
+use crate::app::wasm::local::secrets::Secrets;
+use crate::app::wasm::local::signature::SignatureVerifier;
+use crate::app::wasm::local::source::ResolvedSource;
+use crate::app::wasm::{self, ExecutionLimits, LogOptions, Runner, WasmError};
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
+use oci_distribution::Reference;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
 use tracing::debug_span;
-use wasmtime::{Engine, Module};
+use wasmtime::{Engine, InstanceAllocationStrategy, PoolingAllocationConfig};
 use workflow_model::model::{PluginInvocation, PluginResult, S3ArtifactRepositoryConfig};
 
 pub mod cache;
+mod guest_memory;
+mod http;
 mod image;
 mod interface;
+pub mod kv;
+mod logging;
+mod lru;
+mod mounts;
+mod output;
+mod progress;
+mod secrets;
+pub mod signature;
+mod source;
+mod wit;
 
+/// Compilation of a module that concurrent requests wait for
+type Pull = Arc<OnceCell<Arc<Vec<u8>>>>;
+
+/// LocalRunner runs modules in the plugin process. It is shared by all requests, so that they use
+/// the same Engine and the modules it compiled.
 pub struct LocalRunner {
-    cache: Box<dyn ModuleCache + Send + Sync>,
-    insecure_oci_registries: Vec<String>,
+    engine: Engine,
+    _ticker: EpochTicker,
+    linker: Linkers,
+    /// Fingerprint of the engine that precompiled modules are cached by
+    fingerprint: String,
+    /// Cache of precompiled modules, which is local to the plugin
+    cache: DynModuleCache,
+    /// Cache of the Wasm bytes of modules, which may be shared with other plugins
+    wasm_cache: DynModuleCache,
+    modules: Mutex<Lru<Linked>>,
+    /// Compilations of modules that are not cached yet, by cache key. Concurrent requests of a
+    /// module wait for the same compilation.
+    pulls: Mutex<HashMap<String, Pull>>,
+    kv: DynKVStore,
+    config: Config,
+    client: Option<kube::Client>,
+    namespace: Option<String>,
+}
+
+pub struct Config {
+    pub insecure_oci_registries: Vec<String>,
+    pub default_limits: ExecutionLimits,
+    pub registry_credentials: RegistryCredentials,
+    pub signature_verifier: SignatureVerifier,
+    /// Number of linked modules kept in memory
+    pub module_cache_size: usize,
+    /// Maximum number of bytes of stdout and stderr returned as outputs
+    pub log_max_size: usize,
+    /// Host paths that templates may mount into modules
+    pub mount_allowlist: Vec<PathBuf>,
+}
+
+/// Pooling configures wasmtime's pooling instance allocator, which reserves the memory of a fixed
+/// number of instances up front and reuses it
+#[derive(Clone, Debug)]
+pub struct Pooling {
+    /// Number of instances that can exist at the same time
+    pub instances: u32,
+    /// Bytes of linear memory reserved for each instance, which also limits the module's memory
+    pub memory_reservation: u64,
 }
 
 impl LocalRunner {
-    pub fn new(
-        cache: Box<dyn ModuleCache + Send + Sync>,
-        insecure_oci_registries: Vec<String>,
-    ) -> Self {
-        LocalRunner {
+    pub fn try_new(
+        engine: Engine,
+        cache: DynModuleCache,
+        wasm_cache: DynModuleCache,
+        kv: DynKVStore,
+        config: Config,
+        client: Option<kube::Client>,
+        namespace: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let linker = interface::create_linker(&engine)?;
+        Ok(LocalRunner {
+            fingerprint: interface::engine_fingerprint(&engine),
+            _ticker: EpochTicker::start(&engine),
+            engine,
+            linker,
             cache,
+            wasm_cache,
+            modules: Mutex::new(Lru::new(config.module_cache_size)),
+            pulls: Mutex::new(HashMap::new()),
+            kv,
+            config,
+            client,
+            namespace,
+        })
+    }
+
+    /// Returns the configured registry credentials, overridden by the template's image pull secrets
+    async fn registry_credentials(
+        &self,
+        image_pull_secrets: &Option<Vec<ImagePullSecret>>,
+    ) -> anyhow::Result<RegistryCredentials> {
+        let mut credentials = self.config.registry_credentials.clone();
+        let image_pull_secrets = match image_pull_secrets {
+            Some(secrets) if !secrets.is_empty() => secrets,
+            _ => return Ok(credentials),
+        };
+        let client = self.client()?;
+        for secret in image_pull_secrets {
+            let secret_credentials =
+                k8s::fetch_registry_credentials(client, self.namespace.as_deref(), &secret.name)
+                    .await
+                    .context(format!("Fetching image pull secret {}", secret.name))?;
+            credentials.extend(secret_credentials);
+        }
+        Ok(credentials)
+    }
+
+    fn insecure_oci_registries(&self) -> Vec<&str> {
+        self.config
+            .insecure_oci_registries
+            .iter()
+            .map(|i| i.as_str())
+            .collect()
+    }
+
+    fn client(&self) -> anyhow::Result<&kube::Client> {
+        self.client
+            .as_ref()
+            .ok_or_else(|| anyhow!("Kube client is required for this module source"))
+    }
+
+    /// Resolves the module source to exactly one module, fetching the module if that is required
+    /// to identify it
+    async fn resolve_source<'a>(
+        &self,
+        config: &WasmPluginConfig,
+        insecure_oci_registries: &'a [&'a str],
+    ) -> anyhow::Result<ResolvedSource, WasmError> {
+        match &config.module {
+            ModuleSource::OCI(image) => {
+                let reference = image::parse_reference(image).map_err(WasmError::Retrieve)?;
+                // Credentials are only required when talking to the registry
+                let (digest, credentials) = match reference.digest() {
+                    Some(digest) => (digest.to_owned(), None),
+                    None => {
+                        let credentials = self
+                            .registry_credentials(&config.image_pull_secrets)
+                            .await
+                            .map_err(|err| {
+                                WasmError::Retrieve(
+                                    err.context("Resolving registry credentials failed"),
+                                )
+                            })?;
+                        let digest = image::resolve_digest(
+                            &reference,
+                            &credentials,
+                            insecure_oci_registries,
+                        )
+                        .await
+                        .map_err(|err| {
+                            WasmError::Retrieve(
+                                anyhow!(err).context("Resolving Wasm module digest failed"),
+                            )
+                        })?;
+                        (digest, Some(credentials))
+                    }
+                };
+                Ok(ResolvedSource::OCI {
+                    reference,
+                    digest,
+                    credentials,
+                })
+            }
+            ModuleSource::HTTP(http) => Ok(ResolvedSource::HTTP {
+                url: http.url.to_owned(),
+                digest: format!("sha256:{}", http.sha256.to_lowercase()),
+            }),
+            ModuleSource::ConfigMap(key_ref) => {
+                let data = k8s::fetch_config_map_key(
+                    self.client().map_err(WasmError::Retrieve)?,
+                    self.namespace.as_deref(),
+                    &key_ref.name,
+                    &key_ref.key,
+                )
+                .await
+                .map_err(|err| {
+                    WasmError::Retrieve(
+                        err.context(format!("Fetching module from ConfigMap {}", key_ref.name)),
+                    )
+                })?;
+                let name = format!("configmap/{}/{}", key_ref.name, key_ref.key);
+                Ok(ResolvedSource::fetched(name, data))
+            }
+            ModuleSource::Secret(key_ref) => {
+                let data = k8s::fetch_secret_key(
+                    self.client().map_err(WasmError::Retrieve)?,
+                    self.namespace.as_deref(),
+                    &key_ref.name,
+                    &key_ref.key,
+                )
+                .await
+                .map_err(|err| {
+                    WasmError::Retrieve(
+                        err.context(format!("Fetching module from Secret {}", key_ref.name)),
+                    )
+                })?;
+                let name = format!("secret/{}/{}", key_ref.name, key_ref.key);
+                Ok(ResolvedSource::fetched(name, data))
+            }
+            ModuleSource::Inline(module) => {
+                let data = base64::decode(module).map_err(|err| {
+                    WasmError::Retrieve(anyhow!(err).context("Decoding inline module failed"))
+                })?;
+                Ok(ResolvedSource::fetched("inline".to_string(), data))
+            }
+        }
+    }
+
+    /// Returns the module of a resolved source, pulling and verifying it if necessary
+    async fn fetch_source<'a>(
+        &self,
+        source: ResolvedSource,
+        config: &WasmPluginConfig,
+        insecure_oci_registries: &'a [&'a str],
+    ) -> anyhow::Result<Vec<u8>, WasmError> {
+        match source {
+            ResolvedSource::OCI {
+                reference,
+                digest,
+                credentials,
+            } => {
+                let credentials = self.source_credentials(credentials, config).await?;
+                let pinned_image = image::pinned_reference(&reference, &digest);
+                let pulled_mod = pull(&pinned_image, &credentials, insecure_oci_registries)
+                    .await
+                    .map_err(|err| {
+                        WasmError::Retrieve(anyhow!(err).context("Wasm module retrieve failed"))
+                    })?;
+                self.verify_signature(&reference, &digest, &credentials, insecure_oci_registries)
+                    .await?;
+                Ok(pulled_mod)
+            }
+            ResolvedSource::HTTP { url, digest } => source::fetch_http(&url, &digest)
+                .await
+                .map_err(|err| WasmError::Retrieve(err.context("Wasm module retrieve failed"))),
+            ResolvedSource::Fetched { data, .. } => Ok(data),
+        }
+    }
+
+    /// Returns the Wasm bytes of the module from the ModuleCache if they match the resolved source.
+    /// The cache may be shared with other plugins, so the bytes are verified like after a pull and
+    /// the signature is checked again before the module is compiled.
+    async fn cached_wasm<'a>(
+        &self,
+        source: &ResolvedSource,
+        wasm_key: &str,
+        config: &WasmPluginConfig,
+        insecure_oci_registries: &'a [&'a str],
+    ) -> anyhow::Result<Option<Vec<u8>>, WasmError> {
+        let wasm = match self.wasm_cache.get(wasm_key).await.map_err(|err| {
+            WasmError::EnvironmentSetup(anyhow!(err).context("Checking Wasm module cache failed"))
+        })? {
+            Some(wasm) => wasm,
+            None => return Ok(None),
+        };
+        let verified = match source {
+            ResolvedSource::OCI {
+                reference,
+                digest,
+                credentials,
+            } => {
+                let credentials = self.source_credentials(credentials.clone(), config).await?;
+                let pinned_image =
+                    image::parse_reference(&image::pinned_reference(reference, digest))
+                        .map_err(WasmError::Retrieve)?;
+                match image::verify_oci_image(
+                    &pinned_image,
+                    &wasm,
+                    &credentials,
+                    insecure_oci_registries,
+                )
+                .await
+                {
+                    Ok(_) => {
+                        self.verify_signature(
+                            reference,
+                            digest,
+                            &credentials,
+                            insecure_oci_registries,
+                        )
+                        .await?;
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            ResolvedSource::HTTP { digest, .. } => source::verify_digest(&wasm, digest),
+            // The module was fetched to be identified already
+            ResolvedSource::Fetched { .. } => return Ok(None),
+        };
+        match verified {
+            Ok(_) => Ok(Some(wasm)),
+            Err(err) => {
+                tracing::warn!(?err, "Fetching Wasm module that does not match the cache");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the credentials the source was resolved with, or resolves them
+    async fn source_credentials(
+        &self,
+        credentials: Option<RegistryCredentials>,
+        config: &WasmPluginConfig,
+    ) -> anyhow::Result<RegistryCredentials, WasmError> {
+        match credentials {
+            Some(credentials) => Ok(credentials),
+            None => self
+                .registry_credentials(&config.image_pull_secrets)
+                .await
+                .map_err(|err| {
+                    WasmError::Retrieve(err.context("Resolving registry credentials failed"))
+                }),
+        }
+    }
+
+    /// Resolves the arguments and environment variables of the template
+    async fn resolve_environment(
+        &self,
+        config: &WasmPluginConfig,
+        invocation: &PluginInvocation,
+        module_name: &str,
+    ) -> anyhow::Result<ModuleEnvironment> {
+        let mut env: Vec<(String, String)> = Vec::new();
+        for var in config.env.iter().flatten() {
+            let value = match (&var.value, &var.value_from) {
+                (Some(value), _) => value.to_owned(),
+                (None, Some(EnvVarSource::Parameter(name))) => {
+                    wasm::parameter_value(invocation, name)
+                        .context(format!("Resolving environment variable {}", var.name))?
+                }
+                (None, Some(EnvVarSource::SecretKeyRef(key_ref))) => {
+                    let value = k8s::fetch_secret_key(
+                        self.client()?,
+                        self.namespace.as_deref(),
+                        &key_ref.name,
+                        &key_ref.key,
+                    )
+                    .await
+                    .context(format!("Resolving environment variable {}", var.name))?;
+                    String::from_utf8(value).context(format!(
+                        "Secret {} for environment variable {} is not valid UTF-8",
+                        key_ref.name, var.name
+                    ))?
+                }
+                (None, None) => {
+                    return Err(anyhow!(format!(
+                        "Environment variable {} needs value or value_from",
+                        var.name
+                    )))
+                }
+            };
+            env.push((var.name.to_owned(), value));
+        }
+        let mut secrets = Secrets::default();
+        let permissions = config
+            .permissions
+            .as_ref()
+            .and_then(|perms| perms.secrets.as_ref());
+        for permission in permissions.into_iter().flatten() {
+            let value = k8s::fetch_secret_key(
+                self.client()?,
+                self.namespace.as_deref(),
+                &permission.name,
+                &permission.key,
+            )
+            .await
+            .context(format!(
+                "Fetching secret {}/{}",
+                permission.name, permission.key
+            ))?;
+            secrets.add(permission, value)?;
+        }
+        let mount_permissions = config
+            .permissions
+            .as_ref()
+            .and_then(|perms| perms.filesystem.as_ref())
+            .map(|filesystem| filesystem.mounts.as_slice())
+            .unwrap_or_default();
+        let mounts = mounts::resolve_mounts(&self.config.mount_allowlist, mount_permissions)?;
+        let http = self.resolve_http_policy(config, &mut secrets).await?;
+        let kv = config
+            .permissions
+            .as_ref()
+            .and_then(|perms| perms.kv.as_ref())
+            .map(|permissions| KVCtx::new(self.kv.clone(), &invocation.workflow_name, permissions))
+            .unwrap_or_default();
+        let log = LogCtx::new(&invocation.workflow_name, module_name, secrets.redactor());
+        Ok(ModuleEnvironment {
+            args: config.args.to_owned().unwrap_or_default(),
+            env,
+            secrets,
+            mounts,
+            http,
+            kv,
+            log,
+        })
+    }
+
+    /// Resolves the HttpPolicy of the template, fetching the values of the headers to inject
+    async fn resolve_http_policy(
+        &self,
+        config: &WasmPluginConfig,
+        secrets: &mut Secrets,
+    ) -> anyhow::Result<HttpPolicy> {
+        let http_permissions = match config
+            .permissions
+            .as_ref()
+            .and_then(|perms| perms.http.as_ref())
+        {
+            Some(http_permissions) => http_permissions,
+            None => return Ok(HttpPolicy::default()),
+        };
+        let mut headers: Vec<InjectedHeader> = Vec::new();
+        for header in http_permissions.headers.iter().flatten() {
+            let value = k8s::fetch_secret_key(
+                self.client()?,
+                self.namespace.as_deref(),
+                &header.secret_key_ref.name,
+                &header.secret_key_ref.key,
+            )
+            .await
+            .context(format!("Resolving HTTP header {}", header.name))?;
+            let value = String::from_utf8(value).context(format!(
+                "Secret {} for HTTP header {} is not valid UTF-8",
+                header.secret_key_ref.name, header.name
+            ))?;
+            secrets.add_redacted(value.as_bytes());
+            headers.push(InjectedHeader {
+                host: header.host.to_owned(),
+                name: header.name.to_owned(),
+                value: format!("{}{}", header.prefix.as_deref().unwrap_or_default(), value),
+            });
+        }
+        HttpPolicy::try_new(http_permissions, headers)
+    }
+
+    /// Returns the linked module from memory, or loads and links it
+    async fn link_module(
+        &self,
+        source: ResolvedSource,
+        config: &WasmPluginConfig,
+    ) -> anyhow::Result<Linked, WasmError> {
+        let cache_key = source.cache_key();
+        let module_key = self.module_key(&source);
+        let cached_module = self.modules.lock().unwrap().get(&module_key);
+        if let Some(linked) = cached_module {
+            return Ok(linked);
+        }
+        let compiled = self
+            .load_module(source, &cache_key, &module_key, config)
+            .await?;
+        let linked = debug_span!("engine.instantiate_pre")
+            .in_scope(|| interface::instantiate_pre(&self.linker, &compiled))
+            .map_err(|err| WasmError::EnvironmentSetup(err.context("Linking module failed")))?;
+        self.modules
+            .lock()
+            .unwrap()
+            .put(&module_key, linked.clone());
+        Ok(linked)
+    }
+
+    /// Returns the key of the compiled module in memory and in the ModuleCache. Signatures are
+    /// only checked when a module is compiled, so modules from OCI registries are keyed by the
+    /// signature policy and public keys they were checked with as well. Once these change, the
+    /// module is verified and compiled again.
+    fn module_key(&self, source: &ResolvedSource) -> String {
+        let cache_key = source.cache_key();
+        let tag = match source {
+            ResolvedSource::OCI { reference, .. } => {
+                let repository = format!("{}/{}", reference.registry(), reference.repository());
+                self.config.signature_verifier.cache_tag(&repository)
+            }
+            _ => None,
+        };
+        match tag {
+            Some(tag) => format!("{}-{}", cache_key, tag),
+            None => cache_key,
+        }
+    }
+
+    /// Returns the compiled module from the ModuleCache, or compiles it. Precompiled modules are
+    /// cached by the fingerprint of the engine, so they are compiled again from their Wasm bytes
+    /// once wasmtime or the settings of the engine change.
+    async fn load_module(
+        &self,
+        source: ResolvedSource,
+        cache_key: &str,
+        module_key: &str,
+        config: &WasmPluginConfig,
+    ) -> anyhow::Result<Compiled, WasmError> {
+        let precompiled_key = format!("{}-{}", module_key, self.fingerprint);
+        let cached: Option<Vec<u8>> = self.cache.get(&precompiled_key).await.map_err(|err| {
+            WasmError::EnvironmentSetup(anyhow!(err).context("Checking Wasm module cache failed"))
+        })?;
+        if let Some(precompiled) = cached {
+            match self.deserialize(&precompiled) {
+                Ok(compiled) => return Ok(compiled),
+                Err(err) => tracing::warn!(?err, "Compiling cached Wasm module again"),
+            }
+        }
+        let precompiled = self
+            .compile_module(source, cache_key, &precompiled_key, config)
+            .await?;
+        self.deserialize(&precompiled)
+    }
+
+    fn deserialize(&self, precompiled: &[u8]) -> anyhow::Result<Compiled, WasmError> {
+        debug_span!("engine.deserialize_mod").in_scope(|| {
+            interface::deserialize(&self.engine, precompiled).map_err(|err| {
+                WasmError::EnvironmentSetup(anyhow!(err).context("Deserializing module failed"))
+            })
+        })
+    }
+
+    /// Precompiles and caches the module. The Wasm bytes of pulled modules are cached as well, so
+    /// the module is only pulled if they are not cached yet. Concurrent requests of the same
+    /// module wait for the compilation that started first instead of compiling the module again.
+    /// If that compilation fails, the next waiting request retries it.
+    async fn compile_module(
+        &self,
+        source: ResolvedSource,
+        cache_key: &str,
+        precompiled_key: &str,
+        config: &WasmPluginConfig,
+    ) -> anyhow::Result<Arc<Vec<u8>>, WasmError> {
+        let pull = self
+            .pulls
+            .lock()
+            .unwrap()
+            .entry(precompiled_key.to_owned())
+            .or_default()
+            .clone();
+        let result = pull
+            .get_or_try_init(|| async {
+                let wasm_key = format!("{}.wasm", cache_key);
+                let insecure_oci_registries = self.insecure_oci_registries();
+                let cached_wasm = self
+                    .cached_wasm(&source, &wasm_key, config, &insecure_oci_registries)
+                    .await?;
+                let wasm = match cached_wasm {
+                    Some(wasm) => wasm,
+                    None => {
+                        // Modules from ConfigMaps, Secrets and inline sources are fetched anyway
+                        let shared = !matches!(source, ResolvedSource::Fetched { .. });
+                        let fetched_mod = self
+                            .fetch_source(source, config, &insecure_oci_registries)
+                            .await?;
+                        if shared {
+                            self.wasm_cache
+                                .put(&wasm_key, &fetched_mod)
+                                .await
+                                .map_err(|err| {
+                                    WasmError::Retrieve(
+                                        anyhow!(err).context("Storing Wasm module in cache failed"),
+                                    )
+                                })?;
+                        }
+                        fetched_mod
+                    }
+                };
+                let precompiled_mod = debug_span!("engine.precompile_module").in_scope(|| {
+                    interface::precompile(&self.engine, &wasm).map_err(|err| {
+                        WasmError::Precompile(
+                            anyhow!(err).context("Wasm module precompilation failed"),
+                        )
+                    })
+                })?;
+                self.cache
+                    .put(precompiled_key, &precompiled_mod)
+                    .await
+                    .map_err(|err| {
+                        WasmError::Retrieve(
+                            anyhow!(err).context("Storing Wasm module in cache failed"),
+                        )
+                    })?;
+                Ok(Arc::new(precompiled_mod))
+            })
+            .await
+            .map(Arc::clone);
+
+        // Later requests find the module in the cache, unless a newer pull already replaced this one
+        let mut pulls = self.pulls.lock().unwrap();
+        if matches!(pulls.get(precompiled_key), Some(current) if Arc::ptr_eq(current, &pull)) {
+            pulls.remove(precompiled_key);
+        }
+        result
+    }
+
+    /// Checks the signature of the module according to the policy matching its repository
+    #[tracing::instrument(
+        name = "wasm.verify_signature",
+        level = "debug",
+        skip(self, reference, registry_credentials, insecure_oci_registries)
+    )]
+    async fn verify_signature<'a>(
+        &self,
+        reference: &Reference,
+        digest: &str,
+        registry_credentials: &RegistryCredentials,
+        insecure_oci_registries: &'a [&'a str],
+    ) -> anyhow::Result<(), WasmError> {
+        let repository = format!("{}/{}", reference.registry(), reference.repository());
+        let mode = self.config.signature_verifier.mode(&repository);
+        if mode == SignatureMode::Off {
+            return Ok(());
+        }
+        let verified = match image::fetch_signatures(
+            reference,
+            digest,
+            registry_credentials,
             insecure_oci_registries,
+        )
+        .await
+        {
+            Ok(signatures) => self.config.signature_verifier.verify(digest, &signatures),
+            Err(err) => Err(err.context("Fetching signatures failed")),
+        };
+        match verified {
+            Ok(_) => Ok(()),
+            Err(err) if mode == SignatureMode::Warn => {
+                tracing::warn!(?err, %repository, "Running Wasm module without valid signature");
+                Ok(())
+            }
+            Err(err) => Err(WasmError::Rejected(err)),
         }
     }
 }
@@ -33,80 +665,526 @@ impl LocalRunner {
 impl Runner for LocalRunner {
     #[tracing::instrument(
         name = "wasm.run_local",
-        ret,
         err(Debug),
-        skip(self, artifact_repo_config)
+        skip(self, invocation, artifact_repo_config)
     )]
     async fn run(
         &self,
-        oci_image: &str,
         invocation: PluginInvocation,
-        perms: &Option<ModulePermissions>,
+        config: &WasmPluginConfig,
         artifact_repo_config: Option<S3ArtifactRepositoryConfig>,
     ) -> anyhow::Result<PluginResult, WasmError> {
-        let engine = setup_engine().map_err(WasmError::EnvironmentSetup)?;
-        let mut module: Option<Vec<u8>> = self.cache.get(oci_image).map_err(|err| {
-            WasmError::EnvironmentSetup(anyhow!(err).context("Checking Wasm module cache failed"))
-        })?;
-        if module.is_none() {
-            let insecure_oci_registries: Vec<&str> = self
-                .insecure_oci_registries
-                .iter()
-                .map(|i| i.as_str())
-                .collect();
-            let pulled_mod: Vec<u8> =
-                pull(oci_image, &insecure_oci_registries)
-                    .await
-                    .map_err(|err| {
-                        WasmError::Retrieve(anyhow!(err).context("Wasm module retrieve failed"))
-                    })?;
-            let precompiled_mod = debug_span!("engine.precompile_module").in_scope(|| {
-                engine.precompile_module(&pulled_mod).map_err(|err| {
-                    WasmError::Precompile(anyhow!(err).context("Wasm module precompilation failed"))
-                })
-            })?;
-            let _ = self.cache.put(oci_image, &precompiled_mod).map_err(|err| {
-                WasmError::Retrieve(anyhow!(err).context("Storing Wasm module in cache failed"))
+        let limits = self.config.default_limits.merge(&config.limits);
+        let logs = LogOptions::new(&config.logs, self.config.log_max_size);
+        let insecure_oci_registries = self.insecure_oci_registries();
+        let source = self
+            .resolve_source(config, &insecure_oci_registries)
+            .await?;
+        let module_name = source.name();
+        let linked = self.link_module(source, config).await?;
+
+        let environment = self
+            .resolve_environment(config, &invocation, &module_name)
+            .await
+            .map_err(|err| {
+                WasmError::EnvironmentSetup(err.context("Resolving module environment failed"))
             })?;
-            module = Some(precompiled_mod);
-        }
 
-        let module = debug_span!("engine.deserialize_mod").in_scope(|| {
-            unsafe { Module::deserialize(&engine, module.unwrap()) }.map_err(|err| {
-                WasmError::EnvironmentSetup(anyhow!(err).context("Deserializing module failed"))
-            })
+        let instance = ModuleInstance::try_new(
+            &self.engine,
+            limits,
+            logs,
+            environment,
+            artifact_repo_config,
+        )
+        .await
+        .map_err(|err| {
+            WasmError::EnvironmentSetup(anyhow!(err).context("Creating module store failed"))
         })?;
-
-        // First try to instantiate the module as WIT and fall back to WASI in case of an error
-        let mut plugin: Box<dyn WorkflowPlugin + Send> =
-            match WASIModule::try_new(&engine, &module, perms, artifact_repo_config)
-                .await
-                .map_err(|err| {
-                    WasmError::EnvironmentSetup(anyhow!(err).context("Creating WASI module failed"))
-                }) {
-                Ok(wasi) => Box::new(wasi),
-                Err(e) => return Err(e),
-            };
-        let result = plugin.run(invocation).await.map_err(|err| {
-            WasmError::Invocation(anyhow!(err).context("Wasm module invocation failed"))
+        let mut plugin = interface::instantiate(&linked, instance)
+            .await
+            .map_err(|err| {
+                WasmError::EnvironmentSetup(err.context("Instantiating module failed"))
+            })?;
+        let mut result = plugin.run(invocation).await.map_err(|err| {
+            match err.downcast_ref::<LimitExceeded>() {
+                Some(LimitExceeded::Timeout(_)) => WasmError::Timeout(err),
+                Some(_) => WasmError::ResourceExhausted(err),
+                None => {
+                    WasmError::Invocation(anyhow!(err).context("Wasm module invocation failed"))
+                }
+            }
         })?;
+        // Report the exact module that ran, since sources may point to a different module later
+        result.message = match result.message.is_empty() {
+            true => format!("module: {}", module_name),
+            false => format!("{} (module: {})", result.message, module_name),
+        };
         Ok(result)
     }
+
+    #[tracing::instrument(name = "wasm.prefetch", skip(self))]
+    async fn prefetch(&self, image: &str) -> anyhow::Result<(), WasmError> {
+        let config = WasmPluginConfig {
+            module: ModuleSource::OCI(image.to_owned()),
+            permissions: None,
+            limits: None,
+            image_pull_secrets: None,
+            logs: None,
+            env: None,
+            args: None,
+            extra: HashMap::new(),
+        };
+        let insecure_oci_registries = self.insecure_oci_registries();
+        let source = self
+            .resolve_source(&config, &insecure_oci_registries)
+            .await?;
+        self.link_module(source, &config).await?;
+        Ok(())
+    }
 }
 
-#[tracing::instrument(name = "wasm.oci_pull", level = "debug", skip(insecure_oci_registries))]
+#[tracing::instrument(
+    name = "wasm.oci_pull",
+    level = "debug",
+    skip(registry_credentials, insecure_oci_registries)
+)]
 async fn pull<'a>(
     oci_image_name: &str,
+    registry_credentials: &RegistryCredentials,
     insecure_oci_registries: &'a [&'a str],
 ) -> anyhow::Result<Vec<u8>> {
     // Pull module image, put into Vec<u8>
-    image::fetch_oci_image(oci_image_name, insecure_oci_registries)
+    let reference = image::parse_reference(oci_image_name)?;
+    image::fetch_oci_image(&reference, registry_credentials, insecure_oci_registries)
         .await
         .map_err(|err| anyhow!(err).context("Could not fetch Wasm OCI image"))
 }
 
-pub fn setup_engine() -> anyhow::Result<Engine> {
+pub fn setup_engine(pooling: &Option<Pooling>) -> anyhow::Result<Engine> {
     let mut config = wasmtime::Config::new();
     config.async_support(true);
+    config.consume_fuel(true);
+    config.epoch_interruption(true);
+    if let Some(pooling) = pooling {
+        config.static_memory_maximum_size(pooling.memory_reservation);
+        // A component consists of several core instances, e.g. its module and the WASI adapter
+        let core_instances = pooling.instances * CORE_INSTANCES_PER_COMPONENT;
+        let mut pooling_config = PoolingAllocationConfig::default();
+        pooling_config
+            .total_component_instances(pooling.instances)
+            .total_core_instances(core_instances)
+            .total_memories(core_instances)
+            .total_tables(core_instances)
+            .max_memory_size(pooling.memory_reservation as usize);
+        config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling_config));
+    }
     Engine::new(&config)
 }
+
+/// Number of core instances reserved per instance in the pooling allocator
+const CORE_INSTANCES_PER_COMPONENT: u32 = 4;
+
+/// Interval in which the epoch of an engine is incremented. Running modules yield to the async
+/// executor on every epoch, which allows their execution to be cancelled by a timeout.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// EpochTicker increments the epoch of an engine until it is dropped
+struct EpochTicker {
+    handle: JoinHandle<()>,
+}
+
+impl EpochTicker {
+    fn start(engine: &Engine) -> Self {
+        let engine = engine.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EPOCH_TICK);
+            loop {
+                interval.tick().await;
+                engine.increment_epoch();
+            }
+        });
+        EpochTicker { handle }
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::registry::RegistryCredentials;
+    use crate::app::model::WasmPluginConfig;
+    use crate::app::wasm::local::cache::{self, new_fs_cache, CacheMetrics};
+    use crate::app::wasm::local::interface::{
+        self, Compiled, LimitExceeded, Linked, ModuleEnvironment, ModuleInstance, WorkflowPlugin,
+    };
+    use crate::app::wasm::local::kv::{self, MemoryLimits};
+    use crate::app::wasm::local::signature::SignatureVerifier;
+    use crate::app::wasm::local::source::{self, ResolvedSource};
+    use crate::app::wasm::local::{
+        setup_engine, Config, EpochTicker, LocalRunner, Pooling, CORE_INSTANCES_PER_COMPONENT,
+    };
+    use crate::app::wasm::{ExecutionLimits, LogOptions, Progress};
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wasmtime::{Engine, Module};
+    use workflow_model::model::{Phase, PluginInvocation, PluginResult};
+
+    const RESULT: &str =
+        r#"{"phase":"Succeeded","message":"done","outputs":{"artifacts":[],"parameters":[]}}"#;
+
+    /// Returns a WASI command that runs body and then writes RESULT to the result file in its
+    /// working dir, which is the first preopened dir
+    fn command(body: &str) -> String {
+        format!(
+            r#"(module
+                (import "wasi_snapshot_preview1" "path_open"
+                    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_experimental_http" "req"
+                    (func $req (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+                (import "workflow_progress" "report"
+                    (func $report (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "result.json")
+                (data (i32.const 16) "{result}")
+                (data (i32.const 256) "GET")
+                (data (i32.const 272) "https://example.com/data")
+                (data (i32.const 320) "hello world\n")
+                (data (i32.const 352) "stdout.log")
+                (data (i32.const 368) "Fetching")
+                (func (export "_start")
+                    {body}
+                    (if (call $path_open (i32.const 3) (i32.const 0) (i32.const 0) (i32.const 11)
+                            (i32.const 9) (i64.const 64) (i64.const 0) (i32.const 0)
+                            (i32.const 200))
+                        (then unreachable))
+                    (i32.store (i32.const 208) (i32.const 16))
+                    (i32.store (i32.const 212) (i32.const {len}))
+                    (drop (call $fd_write (i32.load (i32.const 200)) (i32.const 208) (i32.const 1)
+                        (i32.const 216)))))"#,
+            body = body,
+            result = RESULT.replace('"', "\\\""),
+            len = RESULT.len(),
+        )
+    }
+
+    fn link(engine: &Engine, wat: &str) -> Linked {
+        let linker = interface::create_linker(engine).unwrap();
+        let module = Module::new(engine, wat).unwrap();
+        interface::instantiate_pre(&linker, &Compiled::Module(module)).unwrap()
+    }
+
+    async fn instantiate(
+        engine: &Engine,
+        linked: &Linked,
+        limits: ExecutionLimits,
+        logs: LogOptions,
+    ) -> anyhow::Result<Box<dyn WorkflowPlugin + Send>> {
+        let environment = ModuleEnvironment::default();
+        let instance = ModuleInstance::try_new(engine, limits, logs, environment, None).await?;
+        interface::instantiate(linked, instance).await
+    }
+
+    async fn run(
+        engine: &Engine,
+        linked: &Linked,
+        limits: ExecutionLimits,
+        logs: LogOptions,
+    ) -> anyhow::Result<PluginResult> {
+        let invocation = PluginInvocation {
+            workflow_name: "hello-abc12".to_string(),
+            parameters: Vec::new(),
+            artifacts: Vec::new(),
+            plugin_options: Vec::new(),
+        };
+        let mut plugin = instantiate(engine, linked, limits, logs).await?;
+        plugin.run(invocation).await
+    }
+
+    // The EpochTicker needs a thread of its own while the module runs
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_limits() {
+        let engine = setup_engine(&None).unwrap();
+        let _ticker = EpochTicker::start(&engine);
+        let endless = link(&engine, &command("(loop br 0)"));
+
+        let limits = ExecutionLimits {
+            fuel: Some(100_000),
+            ..ExecutionLimits::default()
+        };
+        let err = run(&engine, &endless, limits, LogOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(LimitExceeded::Fuel(100_000))
+        ));
+
+        let limits = ExecutionLimits {
+            timeout: Some(Duration::from_millis(100)),
+            ..ExecutionLimits::default()
+        };
+        let err = run(&engine, &endless, limits, LogOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(LimitExceeded::Timeout(_))
+        ));
+
+        let grow = link(
+            &engine,
+            &command(
+                "(if (i32.lt_s (memory.grow (i32.const 16)) (i32.const 0)) (then unreachable))",
+            ),
+        );
+        let limits = ExecutionLimits {
+            memory: Some(2 * 65536),
+            ..ExecutionLimits::default()
+        };
+        let err = run(&engine, &grow, limits, LogOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(LimitExceeded::Memory(131072))
+        ));
+        let result = run(
+            &engine,
+            &grow,
+            ExecutionLimits::default(),
+            LogOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(Phase::Succeeded, result.phase);
+    }
+
+    #[tokio::test]
+    async fn test_sys_output() {
+        let engine = setup_engine(&None).unwrap();
+        let print = r#"(i32.store (i32.const 224) (i32.const 320))
+            (i32.store (i32.const 228) (i32.const 12))
+            (drop (call $fd_write (i32.const 1) (i32.const 224) (i32.const 1) (i32.const 232)))
+            (drop (call $fd_write (i32.const 2) (i32.const 224) (i32.const 1) (i32.const 232)))"#;
+        let linked = link(&engine, &command(print));
+        let logs = LogOptions {
+            parameters: true,
+            max_size: 5,
+            ..LogOptions::default()
+        };
+        let result = run(&engine, &linked, ExecutionLimits::default(), logs)
+            .await
+            .unwrap();
+
+        let output: Vec<(&str, &serde_json::Value)> = result
+            .outputs
+            .parameters
+            .iter()
+            .map(|parameter| (parameter.name.as_str(), &parameter.value))
+            .collect();
+        let truncated = "hello\n[output truncated after 5 bytes, 7 bytes dropped]\n";
+        assert_eq!(
+            vec![("stdout", &json!(truncated)), ("stderr", &json!(truncated))],
+            output
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sys_output_file() {
+        let engine = setup_engine(&None).unwrap();
+        // Writes 60000 bytes to stdout.log in the working dir, then prints to stdout
+        let write = r#"(if (call $path_open (i32.const 3) (i32.const 0) (i32.const 352) (i32.const 10)
+                    (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 240))
+                (then unreachable))
+            (i32.store (i32.const 244) (i32.const 1024))
+            (i32.store (i32.const 248) (i32.const 60000))
+            (drop (call $fd_write (i32.load (i32.const 240)) (i32.const 244) (i32.const 1)
+                (i32.const 252)))
+            (i32.store (i32.const 224) (i32.const 320))
+            (i32.store (i32.const 228) (i32.const 12))
+            (drop (call $fd_write (i32.const 1) (i32.const 224) (i32.const 1) (i32.const 232)))"#;
+        let linked = link(&engine, &command(write));
+        let logs = LogOptions {
+            parameters: true,
+            max_size: 5,
+            ..LogOptions::default()
+        };
+        let result = run(&engine, &linked, ExecutionLimits::default(), logs)
+            .await
+            .unwrap();
+
+        let stdout = &result.outputs.parameters[0];
+        assert_eq!("stdout", stdout.name);
+        assert_eq!(
+            json!("hello\n[output truncated after 5 bytes, 7 bytes dropped]\n"),
+            stdout.value
+        );
+    }
+
+    #[tokio::test]
+    async fn test_progress_of_failure() {
+        let engine = setup_engine(&None).unwrap();
+        // Reports progress and returns without writing the result file
+        let report = r#"(drop (call $report (i32.const 1) (i32.const 2) (i32.const 368) (i32.const 8)))
+            return"#;
+        let linked = link(&engine, &command(report));
+        let err = run(
+            &engine,
+            &linked,
+            ExecutionLimits::default(),
+            LogOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(Some("1/2".to_string()), Progress::of(&err));
+    }
+
+    #[tokio::test]
+    async fn test_instance_pre_reuse() {
+        let engine = setup_engine(&None).unwrap();
+        let linked = link(&engine, &command(""));
+        for _ in 0..3 {
+            let result = run(
+                &engine,
+                &linked,
+                ExecutionLimits::default(),
+                LogOptions::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!("done", result.message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pooling() {
+        let pooling = Pooling {
+            instances: 1,
+            memory_reservation: 1 << 20,
+        };
+        let engine = setup_engine(&Some(pooling)).unwrap();
+        let linked = link(&engine, &command(""));
+        let mut plugins = Vec::new();
+        for _ in 0..CORE_INSTANCES_PER_COMPONENT {
+            let plugin = instantiate(
+                &engine,
+                &linked,
+                ExecutionLimits::default(),
+                LogOptions::default(),
+            );
+            plugins.push(plugin.await.unwrap());
+        }
+        let exhausted = instantiate(
+            &engine,
+            &linked,
+            ExecutionLimits::default(),
+            LogOptions::default(),
+        );
+        assert!(exhausted.await.is_err());
+
+        // Instances return to the pool once they are dropped
+        plugins.pop();
+        let result = run(
+            &engine,
+            &linked,
+            ExecutionLimits::default(),
+            LogOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(Phase::Succeeded, result.phase);
+    }
+
+    #[tokio::test]
+    async fn test_http_calls() {
+        let engine = setup_engine(&None).unwrap();
+        let request = r#"(drop (call $req (i32.const 272) (i32.const 24) (i32.const 256)
+            (i32.const 3) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)
+            (i32.const 300) (i32.const 304)))"#;
+        let linked = link(&engine, &command(request));
+        let logs = LogOptions {
+            http_calls: true,
+            ..LogOptions::default()
+        };
+        let result = run(&engine, &linked, ExecutionLimits::default(), logs)
+            .await
+            .unwrap();
+
+        let calls = &result
+            .outputs
+            .parameters
+            .iter()
+            .find(|parameter| parameter.name == "http_calls")
+            .unwrap()
+            .value;
+        assert_eq!(1, calls.as_array().unwrap().len());
+        assert_eq!("GET", calls[0]["method"]);
+        assert_eq!("example.com", calls[0]["host"]);
+        assert_eq!("/data", calls[0]["path"]);
+        assert_eq!("Host https://example.com is not allowed", calls[0]["error"]);
+    }
+
+    #[tokio::test]
+    async fn test_recompile_cached_wasm() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = Arc::new(CacheMetrics::default());
+        let cache: cache::DynModuleCache =
+            Arc::new(new_fs_cache(dir.path().to_path_buf(), metrics));
+        let wasm = b"\0asm\x01\0\0\0".to_vec();
+        // The URL cannot be fetched, so the module has to be compiled from the cached Wasm
+        let source = ResolvedSource::HTTP {
+            url: "http://127.0.0.1:9/module.wasm".to_string(),
+            digest: source::sha256_digest(&wasm),
+        };
+        let cache_key = source.cache_key();
+        let previous_engine = Engine::default();
+        let previous_key = format!(
+            "{}-{}",
+            cache_key,
+            interface::engine_fingerprint(&previous_engine)
+        );
+        let previous = interface::precompile(&previous_engine, &wasm).unwrap();
+        cache.put(&previous_key, &previous).await.unwrap();
+        cache
+            .put(&format!("{}.wasm", cache_key), &wasm)
+            .await
+            .unwrap();
+
+        let engine = setup_engine(&None).unwrap();
+        let config = Config {
+            insecure_oci_registries: Vec::new(),
+            default_limits: ExecutionLimits::default(),
+            registry_credentials: RegistryCredentials::default(),
+            signature_verifier: SignatureVerifier::default(),
+            module_cache_size: 1,
+            log_max_size: 1024,
+            mount_allowlist: Vec::new(),
+        };
+        let kv = kv::create_kv_store(&None, &None, None, MemoryLimits::default()).unwrap();
+        let wasm_cache = cache::create_wasm_cache(&cache, None);
+        let runner =
+            LocalRunner::try_new(engine, cache.clone(), wasm_cache, kv, config, None, None)
+                .unwrap();
+        assert_ne!(
+            previous_key,
+            format!("{}-{}", cache_key, runner.fingerprint)
+        );
+
+        let template: WasmPluginConfig =
+            serde_json::from_str(r#"{"module": {"inline": ""}}"#).unwrap();
+        let compiled = runner
+            .load_module(source, &cache_key, &cache_key, &template)
+            .await;
+        assert!(matches!(compiled, Ok(Compiled::Module(_))));
+        let precompiled_key = format!("{}-{}", cache_key, runner.fingerprint);
+        assert!(cache.get(&precompiled_key).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::app::model::{MountMode, MountPermission};
+use anyhow::{anyhow, Context};
+use std::path::{Component, Path, PathBuf};
+use workflow_model::model::WORKING_DIR_PLUGIN_PATH;
+
+/// Mount is a host directory the module may access at guest_path
+#[derive(Debug)]
+pub struct Mount {
+    pub host_path: PathBuf,
+    pub guest_path: String,
+    pub mode: MountMode,
+}
+
+/// Checks the mounts of a template against the allowlist of host paths configured for the plugin
+pub fn resolve_mounts(
+    allowlist: &[PathBuf],
+    permissions: &[MountPermission],
+) -> anyhow::Result<Vec<Mount>> {
+    permissions
+        .iter()
+        .map(|permission| {
+            let host_path = allowed_host_path(allowlist, &permission.host_path)?;
+            let guest_path = permission.guest_path.trim_end_matches('/');
+            if !allowed_guest_path(guest_path) {
+                return Err(anyhow!(format!(
+                    "Cannot mount {} at guest path {}",
+                    permission.host_path, permission.guest_path
+                )));
+            }
+            Ok(Mount {
+                host_path,
+                guest_path: guest_path.to_owned(),
+                mode: permission.mode,
+            })
+        })
+        .collect()
+}
+
+/// Mounts must not shadow the working dir or anything in it, like the secrets dir. Guest paths
+/// must be absolute and normalized, so they cannot reach the working dir through `..`.
+fn allowed_guest_path(guest_path: &str) -> bool {
+    let path = Path::new(guest_path);
+    path.is_absolute()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
+        && !path.starts_with(WORKING_DIR_PLUGIN_PATH)
+}
+
+/// Returns the canonical host path if it is inside of one of the allowed paths. Symlinks are
+/// resolved before checking, so they cannot point outside of the allowed paths.
+fn allowed_host_path(allowlist: &[PathBuf], host_path: &str) -> anyhow::Result<PathBuf> {
+    let canonical = Path::new(host_path)
+        .canonicalize()
+        .context(format!("Resolving host path {}", host_path))?;
+    let allowed = allowlist
+        .iter()
+        .any(|allowed| match allowed.canonicalize() {
+            Ok(allowed) => canonical.starts_with(allowed),
+            Err(_) => false,
+        });
+    if !allowed {
+        return Err(anyhow!(format!(
+            "Host path {} is not in the mount allowlist",
+            host_path
+        )));
+    }
+    Ok(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::{MountMode, MountPermission};
+    use crate::app::wasm::local::mounts::resolve_mounts;
+
+    fn permission(host_path: &str, guest_path: &str) -> MountPermission {
+        MountPermission {
+            host_path: host_path.to_string(),
+            guest_path: guest_path.to_string(),
+            mode: MountMode::ReadOnly,
+        }
+    }
+
+    #[test]
+    fn test_resolve_mounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let allowed = dir.path().join("allowed");
+        let data = allowed.join("data");
+        let other = dir.path().join("other");
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+        let allowlist = vec![allowed];
+
+        let mounts =
+            resolve_mounts(&allowlist, &[permission(data.to_str().unwrap(), "/data/")]).unwrap();
+        assert_eq!(data.canonicalize().unwrap(), mounts[0].host_path);
+        assert_eq!("/data", mounts[0].guest_path);
+
+        let escape = format!("{}/../../other", data.to_str().unwrap());
+        assert!(resolve_mounts(&allowlist, &[permission(&escape, "/data")]).is_err());
+        assert!(
+            resolve_mounts(&allowlist, &[permission(other.to_str().unwrap(), "/data")]).is_err()
+        );
+        assert!(
+            resolve_mounts(&allowlist, &[permission(data.to_str().unwrap(), "/work")]).is_err()
+        );
+        assert!(resolve_mounts(&allowlist, &[permission(data.to_str().unwrap(), "data")]).is_err());
+        for guest_path in [
+            "/",
+            "/work/",
+            "/work/secrets",
+            "/work/out",
+            "//work",
+            "/data/../work",
+        ] {
+            assert!(
+                resolve_mounts(
+                    &allowlist,
+                    &[permission(data.to_str().unwrap(), guest_path)]
+                )
+                .is_err(),
+                "{}",
+                guest_path
+            );
+        }
+        assert!(resolve_mounts(
+            &allowlist,
+            &[permission(data.to_str().unwrap(), "/workspace")]
+        )
+        .is_ok());
+    }
+}
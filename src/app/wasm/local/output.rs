@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use wasmtime_wasi::{HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe};
+
+/// Number of bytes a module may write to an OutputPipe at once
+const WRITE_BUDGET: usize = 64 * 1024;
+
+/// OutputSink streams what a module writes to stdout or stderr into a file, up to max_size
+/// bytes. Output beyond that is counted and dropped, so a chatty module can neither exhaust the
+/// memory nor the disk of the plugin.
+pub struct OutputSink {
+    path: PathBuf,
+    file: File,
+    max_size: u64,
+    written: u64,
+    dropped: u64,
+}
+
+impl OutputSink {
+    pub fn create(path: PathBuf, max_size: u64) -> std::io::Result<Self> {
+        let file = File::create(&path)?;
+        Ok(OutputSink {
+            path,
+            file,
+            max_size,
+            written: 0,
+            dropped: 0,
+        })
+    }
+
+    /// Reads the output back, replacing invalid UTF-8 and marking where it was truncated
+    pub fn contents(&mut self) -> std::io::Result<String> {
+        self.file.flush()?;
+        // Only the bytes written through the sink are read, whatever else changed the file
+        let mut data = Vec::with_capacity(self.written as usize);
+        File::open(&self.path)?
+            .take(self.written)
+            .read_to_end(&mut data)?;
+        let mut output = String::from_utf8_lossy(&data).into_owned();
+        if self.dropped > 0 {
+            output.push_str(&format!(
+                "\n[output truncated after {} bytes, {} bytes dropped]\n",
+                self.written, self.dropped
+            ));
+        }
+        Ok(output)
+    }
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let remaining = self.max_size.saturating_sub(self.written) as usize;
+        let kept = buf.len().min(remaining);
+        if kept > 0 {
+            self.file.write_all(&buf[..kept])?;
+            self.written += kept as u64;
+        }
+        self.dropped += (buf.len() - kept) as u64;
+        // The module is not told about dropped output, it would only retry writing it
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// OutputPipe shares an OutputSink with the WASI context of a module, which uses it as stdout or
+/// stderr
+#[derive(Clone)]
+pub struct OutputPipe(Arc<Mutex<OutputSink>>);
+
+impl OutputPipe {
+    pub fn new(sink: OutputSink) -> Self {
+        OutputPipe(Arc::new(Mutex::new(sink)))
+    }
+
+    /// Reads what the module wrote so far. The WASI context may still hold clones of the pipe,
+    /// so the output is read through the shared sink instead of unwrapping it.
+    pub fn contents(&self) -> std::io::Result<String> {
+        self.0.lock().unwrap().contents()
+    }
+}
+
+impl StdoutStream for OutputPipe {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+impl HostOutputStream for OutputPipe {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .write_all(&bytes)
+            .map_err(|err| StreamError::LastOperationFailed(err.into()))
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .flush()
+            .map_err(|err| StreamError::LastOperationFailed(err.into()))
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(WRITE_BUDGET)
+    }
+}
+
+// Writes never block, so the pipe is always ready
+#[async_trait]
+impl Subscribe for OutputPipe {
+    async fn ready(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::output::OutputSink;
+    use std::io::Write;
+
+    #[test]
+    fn test_output_sink() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut sink = OutputSink::create(dir.path().join("stdout"), 16).unwrap();
+        sink.write_all(b"hello\n").unwrap();
+        sink.write_all(b"w\xF6rld\n").unwrap();
+        assert_eq!("hello\nw\u{FFFD}rld\n", sink.contents().unwrap());
+
+        let mut sink = OutputSink::create(dir.path().join("stderr"), 8).unwrap();
+        sink.write_all(b"hello\n").unwrap();
+        sink.write_all(b"world\n").unwrap();
+        assert_eq!(
+            "hello\nwo\n[output truncated after 8 bytes, 4 bytes dropped]\n",
+            sink.contents().unwrap()
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Progress reporting of modules. Components import the `progress` interface of
+//! `crates/workflow-model/wit/workflow-plugin.wit`, modules import the function `report` of the
+//! module `workflow_progress`, which `workflow_model::plugin::progress` wraps.
+
+use crate::app::wasm::local::guest_memory;
+use crate::app::wasm::local::secrets::Secrets;
+use crate::app::wasm::local::wit::progress as bindings;
+use crate::app::wasm::Progress;
+use async_trait::async_trait;
+use wasmtime::{Caller, Linker};
+
+/// Module that modules import the host function from
+const MODULE: &str = "workflow_progress";
+
+/// ProgressCtx keeps the last progress a module reported
+#[derive(Default)]
+pub struct ProgressCtx {
+    secrets: Secrets,
+    last: Option<Progress>,
+}
+
+impl ProgressCtx {
+    pub fn new(secrets: Secrets) -> Self {
+        ProgressCtx {
+            secrets,
+            last: None,
+        }
+    }
+
+    pub fn take(&mut self) -> Option<Progress> {
+        self.last.take()
+    }
+
+    /// Records the progress, unless more steps are done than there are in total
+    pub fn report(&mut self, done: u32, total: u32, message: &str) -> bool {
+        if total == 0 || done > total {
+            tracing::warn!(done, total, "Module reported invalid progress");
+            return false;
+        }
+        let progress = Progress {
+            done,
+            total,
+            message: self.secrets.redact(message),
+        };
+        tracing::info!(
+            target: "wasm_progress",
+            progress = %progress.fraction(),
+            "{}",
+            progress.message
+        );
+        self.last = Some(progress);
+        true
+    }
+}
+
+/// Error codes of the host function of modules, 0 means success
+#[derive(Debug)]
+enum ErrorCode {
+    MemoryNotFound,
+    MemoryAccessError,
+    Utf8Error,
+    InvalidProgress,
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::MemoryNotFound => 1,
+            ErrorCode::MemoryAccessError => 2,
+            ErrorCode::Utf8Error => 3,
+            ErrorCode::InvalidProgress => 4,
+        }
+    }
+}
+
+/// Defines the host function of the progress reporting for modules
+pub fn add_to_linker<T: Send + 'static>(
+    linker: &mut Linker<T>,
+    get: impl Fn(&mut T) -> &mut ProgressCtx + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.func_wrap(
+        MODULE,
+        "report",
+        move |mut caller: Caller<'_, T>,
+              done: u32,
+              total: u32,
+              message_ptr: u32,
+              message_len: u32| {
+            match report(&mut caller, get, done, total, (message_ptr, message_len)) {
+                Ok(_) => 0,
+                Err(code) => code.into(),
+            }
+        },
+    )?;
+    Ok(())
+}
+
+fn report<T>(
+    caller: &mut Caller<'_, T>,
+    get: impl Fn(&mut T) -> &mut ProgressCtx,
+    done: u32,
+    total: u32,
+    (ptr, len): (u32, u32),
+) -> Result<(), ErrorCode> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or(ErrorCode::MemoryNotFound)?;
+    let buf =
+        guest_memory::read(&memory, &*caller, ptr, len).ok_or(ErrorCode::MemoryAccessError)?;
+    let message = String::from_utf8(buf).map_err(|_| ErrorCode::Utf8Error)?;
+    match get(caller.data_mut()).report(done, total, &message) {
+        true => Ok(()),
+        false => Err(ErrorCode::InvalidProgress),
+    }
+}
+
+#[async_trait]
+impl bindings::Host for ProgressCtx {
+    async fn report(&mut self, done: u32, total: u32, message: String) {
+        ProgressCtx::report(self, done, total, &message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::progress::ProgressCtx;
+    use crate::app::wasm::local::secrets::redacting_secrets;
+    use crate::app::wasm::Progress;
+
+    #[test]
+    fn test_report() {
+        let mut progress = ProgressCtx::new(redacting_secrets());
+
+        assert!(progress.report(1, 10, "Fetching"));
+        assert!(progress.report(3, 10, "Using s3cr3t"));
+        assert!(!progress.report(11, 10, "Too far"));
+        assert!(!progress.report(0, 0, "Empty"));
+
+        let last = progress.take().unwrap();
+        assert_eq!("3/10", last.fraction());
+        assert_eq!("Using [REDACTED]", last.message);
+        assert_eq!(None, progress.take());
+
+        let err = anyhow::anyhow!("wasm trap")
+            .context(last)
+            .context("exceeded fuel limit");
+        assert_eq!("exceeded fuel limit", err.to_string());
+        assert_eq!(Some("3/10".to_string()), Progress::of(&err));
+    }
+}
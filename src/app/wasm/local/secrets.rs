@@ -0,0 +1,182 @@
+use crate::app::model::SecretPermission;
+use anyhow::{anyhow, Context};
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Path at which the secrets that are exposed as files are preopened in the module
+pub const SECRETS_PLUGIN_PATH: &str = "/work/secrets";
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Secrets holds the secrets a module may access. Secrets exposed as files are written to a
+/// directory outside the working dir, which the module can only read from.
+#[derive(Default)]
+pub struct Secrets {
+    dir: Option<TempDir>,
+    env: Vec<(String, String)>,
+    values: Vec<String>,
+}
+
+impl Secrets {
+    /// Exposes the value as environment variable if the permission names one, otherwise as file
+    /// at `/work/secrets/<name>/<key>`
+    pub fn add(&mut self, permission: &SecretPermission, value: Vec<u8>) -> anyhow::Result<()> {
+        self.add_redacted(&value);
+        if let Some(env) = &permission.env {
+            let value = String::from_utf8(value).context(format!(
+                "Secret {} for environment variable {} is not valid UTF-8",
+                permission.name, env
+            ))?;
+            self.env.push((env.to_owned(), value));
+            return Ok(());
+        }
+
+        check_path_segment(&permission.name)?;
+        check_path_segment(&permission.key)?;
+        if self.dir.is_none() {
+            self.dir = Some(TempDir::new().context("Creating secrets dir")?);
+        }
+        let dir = self.dir.as_ref().expect("present secrets dir").path();
+        let secret_dir = dir.join(&permission.name);
+        std::fs::create_dir_all(&secret_dir).context("Creating secret dir")?;
+        std::fs::write(secret_dir.join(&permission.key), value).context(format!(
+            "Writing secret {}/{}",
+            permission.name, permission.key
+        ))?;
+        Ok(())
+    }
+
+    /// Redacts a secret value that is used by the plugin, but not exposed to the module
+    pub fn add_redacted(&mut self, value: &[u8]) {
+        if let Ok(value) = std::str::from_utf8(value) {
+            if !value.is_empty() {
+                self.values.push(value.to_owned());
+                // Debug output escapes quotes, backslashes and control characters
+                let escaped = value.escape_debug().to_string();
+                if escaped != value {
+                    self.values.push(escaped);
+                }
+                // Longer values are replaced first, so no part of a secret that contains another
+                // one is left over
+                self.values
+                    .sort_by_key(|value| std::cmp::Reverse(value.len()));
+            }
+        }
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_ref().map(|dir| dir.path())
+    }
+
+    pub fn env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    /// Returns Secrets that only redact the values of these, for output that is redacted outside
+    /// of the ModuleInstance
+    pub fn redactor(&self) -> Secrets {
+        Secrets {
+            dir: None,
+            env: Vec::new(),
+            values: self.values.clone(),
+        }
+    }
+
+    /// Replaces the values of all secrets in s
+    pub fn redact(&self, s: &str) -> String {
+        self.values
+            .iter()
+            .fold(s.to_owned(), |s, value| s.replace(value, REDACTED))
+    }
+}
+
+/// Keys of Secrets may contain dots, but must not leave the secrets dir
+fn check_path_segment(segment: &str) -> anyhow::Result<()> {
+    if segment.is_empty() || segment == "." || segment == ".." || segment.contains('/') {
+        return Err(anyhow!(format!(
+            "'{}' cannot be used as secret file name",
+            segment
+        )));
+    }
+    Ok(())
+}
+
+/// Redacted formats a value with Debug, replacing the values of the secrets
+pub struct Redacted<'a, T: Debug>(pub &'a T, pub &'a Secrets);
+
+impl<'a, T: Debug> Debug for Redacted<'a, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.1.redact(&format!("{:?}", self.0)))
+    }
+}
+
+/// Returns Secrets with the value `s3cr3t` exposed as environment variable, for tests of the
+/// host functions that redact secrets
+#[cfg(test)]
+pub fn redacting_secrets() -> Secrets {
+    let mut secrets = Secrets::default();
+    let permission = SecretPermission {
+        name: "my-secret".to_string(),
+        key: "token".to_string(),
+        env: Some("TOKEN".to_string()),
+    };
+    secrets.add(&permission, b"s3cr3t".to_vec()).unwrap();
+    secrets
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::SecretPermission;
+    use crate::app::wasm::local::secrets::{Redacted, Secrets};
+
+    #[test]
+    fn test_secrets() {
+        let mut secrets = Secrets::default();
+        let file = SecretPermission {
+            name: "my-secret".to_string(),
+            key: "token.txt".to_string(),
+            env: None,
+        };
+        let env = SecretPermission {
+            name: "my-secret".to_string(),
+            key: "password".to_string(),
+            env: Some("PASSWORD".to_string()),
+        };
+        secrets.add(&file, b"s3cr3t-token".to_vec()).unwrap();
+        secrets.add(&env, b"hunter2".to_vec()).unwrap();
+
+        let path = secrets.dir().unwrap().join("my-secret").join("token.txt");
+        assert_eq!(b"s3cr3t-token".to_vec(), std::fs::read(path).unwrap());
+        assert_eq!(
+            &[("PASSWORD".to_string(), "hunter2".to_string())],
+            secrets.env()
+        );
+        assert_eq!(
+            "\"token [REDACTED], password [REDACTED]\"",
+            format!(
+                "{:?}",
+                Redacted(&"token s3cr3t-token, password hunter2", &secrets)
+            )
+        );
+
+        let escape = SecretPermission {
+            name: "..".to_string(),
+            key: "token".to_string(),
+            env: None,
+        };
+        assert!(secrets.add(&escape, b"value".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_redact_overlapping() {
+        let mut secrets = Secrets::default();
+        secrets.add_redacted(b"abc");
+        secrets.add_redacted(b"abc123");
+        assert_eq!(
+            "[REDACTED] and [REDACTED]",
+            secrets.redact("abc123 and abc")
+        );
+        assert_eq!("[REDACTED]", secrets.redactor().redact("abc123"));
+    }
+}
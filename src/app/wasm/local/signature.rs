@@ -0,0 +1,225 @@
+use crate::app::model::signature::{SignatureMode, SignaturePolicy};
+use anyhow::{anyhow, Context};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::sign::Verifier;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Signature is a cosign-style detached signature of a module
+pub struct Signature {
+    /// Simple signing payload naming the digest of the signed manifest
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct SimpleSigningPayload {
+    critical: SimpleSigningCritical,
+}
+
+#[derive(Deserialize)]
+struct SimpleSigningCritical {
+    image: SimpleSigningImage,
+}
+
+#[derive(Deserialize)]
+struct SimpleSigningImage {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+/// SignatureVerifier checks modules against the configured public keys and policies
+#[derive(Clone, Default)]
+pub struct SignatureVerifier {
+    policies: Vec<SignaturePolicy>,
+    public_keys: Vec<PKey<Public>>,
+}
+
+impl SignatureVerifier {
+    pub fn new(policies: Vec<SignaturePolicy>, public_keys: Vec<PKey<Public>>) -> Self {
+        SignatureVerifier {
+            policies,
+            public_keys,
+        }
+    }
+
+    /// Reads PEM-encoded public keys from the given paths
+    pub fn try_new(
+        policies: Vec<SignaturePolicy>,
+        public_key_paths: &[String],
+    ) -> anyhow::Result<Self> {
+        let public_keys = public_key_paths
+            .iter()
+            .map(|path| {
+                let pem = std::fs::read(path).context(format!("Reading public key {}", path))?;
+                PKey::public_key_from_pem(&pem)
+                    .map_err(|err| anyhow!(err).context(format!("Parsing public key {}", path)))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if public_keys.is_empty()
+            && policies
+                .iter()
+                .any(|policy| policy.mode != SignatureMode::Off)
+        {
+            return Err(anyhow!(
+                "Signature policies require at least one public key"
+            ));
+        }
+        Ok(Self::new(policies, public_keys))
+    }
+
+    /// Returns the mode of the first policy matching the repository, or Off if none matches
+    pub fn mode(&self, repository: &str) -> SignatureMode {
+        self.policies
+            .iter()
+            .find(|policy| policy.matches(repository))
+            .map(|policy| policy.mode)
+            .unwrap_or(SignatureMode::Off)
+    }
+
+    /// Returns a tag that changes with the mode and public keys that modules of the repository
+    /// are checked with, or None if their signatures are not checked
+    pub fn cache_tag(&self, repository: &str) -> Option<String> {
+        let mode = match self.mode(repository) {
+            SignatureMode::Enforce => "enforce",
+            SignatureMode::Warn => "warn",
+            SignatureMode::Off => return None,
+        };
+        let mut keys = Sha256::new();
+        for key in &self.public_keys {
+            keys.update(key.public_key_to_der().unwrap_or_default());
+        }
+        Some(format!("{}-{:.16x}", mode, keys.finalize()))
+    }
+
+    /// Succeeds if one of the signatures is valid for one of the public keys and signs the
+    /// manifest with the given digest
+    pub fn verify(&self, digest: &str, signatures: &[Signature]) -> anyhow::Result<()> {
+        if signatures.is_empty() {
+            return Err(anyhow!(format!("No signature found for {}", digest)));
+        }
+        for signature in signatures {
+            let signed_digest =
+                match serde_json::from_slice::<SimpleSigningPayload>(&signature.payload) {
+                    Ok(payload) => payload.critical.image.docker_manifest_digest,
+                    Err(err) => {
+                        tracing::debug!(?err, "Ignoring signature with invalid payload");
+                        continue;
+                    }
+                };
+            if signed_digest != digest {
+                tracing::debug!(%signed_digest, "Ignoring signature for another manifest");
+                continue;
+            }
+            if self
+                .public_keys
+                .iter()
+                .any(|key| verify_with_key(key, &signature.payload, &signature.signature))
+            {
+                return Ok(());
+            }
+        }
+        Err(anyhow!(format!(
+            "None of {} signatures for {} is valid for the configured public keys",
+            signatures.len(),
+            digest
+        )))
+    }
+}
+
+fn verify_with_key(key: &PKey<Public>, payload: &[u8], signature: &[u8]) -> bool {
+    let verifier = match key.id() {
+        Id::ED25519 => Verifier::new_without_digest(key),
+        _ => Verifier::new(MessageDigest::sha256(), key),
+    };
+    // A signature that cannot be decoded for this kind of key is not valid either
+    match verifier {
+        Ok(mut verifier) => verifier.verify_oneshot(signature, payload).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Returns a new key pair for signing modules in tests
+#[cfg(test)]
+pub fn generate_key() -> (PKey<openssl::pkey::Private>, PKey<Public>) {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let private_key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let public_pem = private_key.public_key_to_pem().unwrap();
+    let public_key = PKey::public_key_from_pem(&public_pem).unwrap();
+    (private_key, public_key)
+}
+
+/// Signs the manifest digest like cosign does, for tests
+#[cfg(test)]
+pub fn sign(key: &PKey<openssl::pkey::Private>, digest: &str) -> Signature {
+    let payload = format!(
+        r#"{{"critical":{{"identity":{{"docker-reference":"ghcr.io/my-org/my-module"}},"image":{{"docker-manifest-digest":"{}"}},"type":"cosign container image signature"}},"optional":null}}"#,
+        digest
+    )
+    .into_bytes();
+    let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), key).unwrap();
+    let signature = signer.sign_oneshot_to_vec(&payload).unwrap();
+    Signature { payload, signature }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::model::signature::SignaturePolicy;
+    use crate::app::wasm::local::signature::{generate_key, sign, SignatureVerifier};
+    use std::str::FromStr;
+
+    const DIGEST: &str = "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    #[test]
+    fn test_verify() {
+        let (private_key, public_key) = generate_key();
+        let (other_private_key, _) = generate_key();
+        let verifier = SignatureVerifier::new(
+            vec![SignaturePolicy::from_str("ghcr.io/my-org/*=enforce").unwrap()],
+            vec![public_key],
+        );
+
+        assert!(verifier
+            .verify(DIGEST, &[sign(&private_key, DIGEST)])
+            .is_ok());
+        assert!(verifier
+            .verify(
+                DIGEST,
+                &[sign(&other_private_key, DIGEST), sign(&private_key, DIGEST)]
+            )
+            .is_ok());
+        assert!(verifier.verify(DIGEST, &[]).is_err());
+        assert!(verifier
+            .verify(DIGEST, &[sign(&other_private_key, DIGEST)])
+            .is_err());
+        assert!(verifier
+            .verify(DIGEST, &[sign(&private_key, "sha256:0000")])
+            .is_err());
+    }
+
+    #[test]
+    fn test_cache_tag() {
+        let (_, public_key) = generate_key();
+        let (_, other_public_key) = generate_key();
+        let policies = vec![
+            SignaturePolicy::from_str("ghcr.io/my-org/*=enforce").unwrap(),
+            SignaturePolicy::from_str("ghcr.io/other/*=warn").unwrap(),
+        ];
+        let verifier = SignatureVerifier::new(policies.clone(), vec![public_key]);
+        let rotated = SignatureVerifier::new(policies, vec![other_public_key]);
+
+        let tag = verifier.cache_tag("ghcr.io/my-org/my-module").unwrap();
+        assert!(tag.starts_with("enforce-"));
+        assert_eq!("enforce-".len() + 16, tag.len());
+        assert!(verifier
+            .cache_tag("ghcr.io/other/my-module")
+            .unwrap()
+            .starts_with("warn-"));
+        assert_eq!(None, verifier.cache_tag("docker.io/library/module"));
+        assert_ne!(Some(tag), rotated.cache_tag("ghcr.io/my-org/my-module"));
+    }
+}
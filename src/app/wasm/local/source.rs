@@ -0,0 +1,98 @@
+use crate::app::model::registry::RegistryCredentials;
+use crate::app::wasm::local::image;
+use anyhow::anyhow;
+use oci_distribution::Reference;
+use sha2::{Digest, Sha256};
+
+/// ResolvedSource is a module source that identifies exactly one module
+#[allow(clippy::upper_case_acronyms)]
+pub enum ResolvedSource {
+    /// OCI image pinned by digest
+    OCI {
+        reference: Reference,
+        digest: String,
+        /// Credentials used for resolving the digest, if any
+        credentials: Option<RegistryCredentials>,
+    },
+    /// URL of a module with a known digest
+    HTTP { url: String, digest: String },
+    /// Module that had to be fetched to be identified
+    Fetched {
+        name: String,
+        digest: String,
+        data: Vec<u8>,
+    },
+}
+
+impl ResolvedSource {
+    pub fn fetched(name: String, data: Vec<u8>) -> Self {
+        ResolvedSource::Fetched {
+            name,
+            digest: sha256_digest(&data),
+            data,
+        }
+    }
+
+    /// Returns the key of the module in the ModuleCache. Modules other than OCI images are
+    /// stored by their digest, so the same module is only precompiled once.
+    pub fn cache_key(&self) -> String {
+        match self {
+            ResolvedSource::OCI {
+                reference, digest, ..
+            } => image::pinned_reference(reference, digest),
+            ResolvedSource::HTTP { digest, .. } => digest.to_owned(),
+            ResolvedSource::Fetched { digest, .. } => digest.to_owned(),
+        }
+    }
+
+    /// Returns a human-readable name of the module including its digest
+    pub fn name(&self) -> String {
+        match self {
+            ResolvedSource::OCI {
+                reference, digest, ..
+            } => image::pinned_reference(reference, digest),
+            ResolvedSource::HTTP { url, digest } => format!("{}@{}", url, digest),
+            ResolvedSource::Fetched { name, digest, .. } => format!("{}@{}", name, digest),
+        }
+    }
+}
+
+pub fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+/// Checks that data hashes to the digest, e.g. `sha256:e3b0c44298fc1c149afbf4c8996fb924...`
+pub fn verify_digest(data: &[u8], digest: &str) -> anyhow::Result<()> {
+    if !digest.starts_with("sha256:") {
+        return Err(anyhow!(format!("Unsupported digest {}", digest)));
+    }
+    let actual = sha256_digest(data);
+    if actual != digest {
+        return Err(anyhow!(format!(
+            "Content does not match digest {}, got {}",
+            digest, actual
+        )));
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "wasm.http_fetch", level = "debug")]
+pub async fn fetch_http(url: &str, digest: &str) -> anyhow::Result<Vec<u8>> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let data = response.bytes().await?.to_vec();
+    verify_digest(&data, digest)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::source::verify_digest;
+
+    #[test]
+    fn test_verify_digest() {
+        let digest = "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        assert!(verify_digest(b"hello world", digest).is_ok());
+        assert!(verify_digest(b"hello world!", digest).is_err());
+        assert!(verify_digest(b"hello world", "md5:5eb63bbbe01eeed093cb22bb8f5acdc3").is_err());
+    }
+}
@@ -0,0 +1,467 @@
+//! Host side of the WIT workflow interface in `crates/workflow-model/wit/workflow-plugin.wit`.
+//! Components are called through bindings generated by wasmtime. Modules built with wit-bindgen
+//! before it generated components pass values in linear memory following the canonical ABI: the
+//! host allocates the invocation with the module's `canonical_abi_realloc` and frees the result
+//! with `canonical_abi_free` after reading it.
+
+use crate::app::wasm::local::guest_memory;
+use crate::app::wasm::local::interface::{Entrypoint, ModuleCtx, ModuleInstance, WorkflowPlugin};
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use wasmtime::{Instance, Memory, Store, TypedFunc};
+use workflow_model::model::{
+    ArtifactRef, Outputs, Parameter, Phase, PluginInvocation, PluginResult,
+};
+
+pub use bindings::argo::workflow_plugin::{kv, log, progress};
+pub use bindings::WorkflowPluginPre;
+
+#[allow(dead_code)]
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "crates/workflow-model/wit",
+        world: "workflow-plugin",
+        async: true,
+    });
+}
+
+const RUN_EXPORT: &str = "run";
+const REALLOC_EXPORT: &str = "canonical_abi_realloc";
+const FREE_EXPORT: &str = "canonical_abi_free";
+const INITIALIZE_EXPORT: &str = "_initialize";
+
+/// Size of the records `parameter` and `artifact-ref`, which both consist of two strings
+const RECORD_SIZE: u32 = 16;
+const RECORD_ALIGN: u32 = 4;
+/// Size of the return area of `run`, which holds a `result<plugin-result, string>`
+const RESULT_SIZE: usize = 32;
+
+/// Returns whether the module implements the WIT workflow interface rather than being a WASI
+/// command
+pub fn is_wit_module(store: &mut Store<ModuleCtx>, instance: &Instance) -> bool {
+    instance.get_func(&mut *store, RUN_EXPORT).is_some()
+        && instance.get_func(&mut *store, REALLOC_EXPORT).is_some()
+}
+
+/// WITModule runs modules and components implementing the WIT workflow interface
+pub struct WITModule {
+    instance: ModuleInstance,
+    exports: WitExports,
+}
+
+impl WITModule {
+    pub async fn try_from_module(
+        mut instance: ModuleInstance,
+        wasm: Instance,
+    ) -> anyhow::Result<Self> {
+        let store = instance.store();
+        let exports = ModuleExports {
+            memory: wasm
+                .get_memory(&mut *store, "memory")
+                .ok_or_else(|| anyhow!("Module does not export its memory"))?,
+            realloc: wasm.get_typed_func(&mut *store, REALLOC_EXPORT)?,
+            free: wasm.get_typed_func(&mut *store, FREE_EXPORT)?,
+            run: wasm.get_typed_func(&mut *store, RUN_EXPORT)?,
+        };
+        // Reactor modules initialize their runtime before any other export is called
+        if let Some(initialize) = wasm.get_func(&mut *store, INITIALIZE_EXPORT) {
+            initialize
+                .typed::<(), ()>(&*store)?
+                .call_async(&mut *store, ())
+                .await
+                .context("Initializing module")?;
+        }
+        Ok(WITModule {
+            instance,
+            exports: WitExports::Module(Box::new(exports)),
+        })
+    }
+
+    pub async fn try_from_component(
+        mut instance: ModuleInstance,
+        plugin_pre: &WorkflowPluginPre<ModuleCtx>,
+    ) -> anyhow::Result<Self> {
+        let plugin = plugin_pre.instantiate_async(instance.store()).await?;
+        Ok(WITModule {
+            instance,
+            exports: WitExports::Component(plugin),
+        })
+    }
+}
+
+#[async_trait]
+impl WorkflowPlugin for WITModule {
+    async fn run(&mut self, invocation: PluginInvocation) -> anyhow::Result<PluginResult> {
+        self.instance
+            .run(invocation, Entrypoint::Wit(&self.exports))
+            .await
+    }
+}
+
+/// WitExports calls `run` of a component through its bindings, or of a module, which was built
+/// with wit-bindgen before it generated components, through the canonical ABI
+pub enum WitExports {
+    Module(Box<ModuleExports>),
+    Component(bindings::WorkflowPlugin),
+}
+
+impl WitExports {
+    /// Calls `run` with the invocation. An error returned by the module becomes a failed result.
+    pub async fn run(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        invocation: &PluginInvocation,
+    ) -> anyhow::Result<PluginResult> {
+        match self {
+            WitExports::Module(exports) => exports.run(store, invocation).await,
+            WitExports::Component(plugin) => {
+                let invocation = bindings::Invocation {
+                    workflow_name: invocation.workflow_name.to_owned(),
+                    plugin_options: to_wit_parameters(&invocation.plugin_options),
+                    parameters: to_wit_parameters(&invocation.parameters),
+                    artifacts: invocation
+                        .artifacts
+                        .iter()
+                        .map(|artifact| bindings::ArtifactRef {
+                            name: artifact.name.to_owned(),
+                            path: artifact.path.to_owned(),
+                        })
+                        .collect(),
+                };
+                match plugin.call_run(&mut *store, &invocation).await? {
+                    Ok(result) => from_wit_result(result),
+                    Err(message) => Ok(PluginResult {
+                        phase: Phase::Failed,
+                        message,
+                        outputs: Outputs::default(),
+                        progress: None,
+                    }),
+                }
+            }
+        }
+    }
+}
+
+fn to_wit_parameters(parameters: &[Parameter]) -> Vec<bindings::Parameter> {
+    parameters
+        .iter()
+        .map(|parameter| bindings::Parameter {
+            name: parameter.name.to_owned(),
+            value: parameter.value.to_string(),
+        })
+        .collect()
+}
+
+fn from_wit_result(result: bindings::PluginResult) -> anyhow::Result<PluginResult> {
+    Ok(PluginResult {
+        phase: match result.phase {
+            bindings::Phase::Succeeded => Phase::Succeeded,
+            bindings::Phase::Failed => Phase::Failed,
+        },
+        message: result.message,
+        outputs: Outputs {
+            artifacts: result
+                .outputs
+                .artifacts
+                .into_iter()
+                .map(|artifact| ArtifactRef {
+                    name: artifact.name,
+                    path: artifact.path,
+                    s3: None,
+                })
+                .collect(),
+            parameters: result
+                .outputs
+                .parameters
+                .into_iter()
+                .map(|parameter| parse_parameter(parameter.name, parameter.value))
+                .collect::<anyhow::Result<Vec<Parameter>>>()?,
+        },
+        progress: None,
+    })
+}
+
+/// Output parameters are returned with JSON-encoded values
+fn parse_parameter(name: String, value: String) -> anyhow::Result<Parameter> {
+    let value = serde_json::from_str(&value).context(format!(
+        "Value of output parameter {} is not valid JSON",
+        name
+    ))?;
+    Ok(Parameter { name, value })
+}
+
+type RunParams = (u32, u32, u32, u32, u32, u32, u32, u32);
+
+pub struct ModuleExports {
+    memory: Memory,
+    realloc: TypedFunc<(u32, u32, u32, u32), u32>,
+    free: TypedFunc<(u32, u32, u32), ()>,
+    run: TypedFunc<RunParams, u32>,
+}
+
+impl ModuleExports {
+    async fn run(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        invocation: &PluginInvocation,
+    ) -> anyhow::Result<PluginResult> {
+        let workflow_name = self.lower_string(store, &invocation.workflow_name).await?;
+        let plugin_options = self
+            .lower_parameters(store, &invocation.plugin_options)
+            .await?;
+        let parameters = self.lower_parameters(store, &invocation.parameters).await?;
+        let artifacts = self.lower_artifacts(store, &invocation.artifacts).await?;
+        let result_ptr = self
+            .run
+            .call_async(
+                &mut *store,
+                (
+                    workflow_name.0,
+                    workflow_name.1,
+                    plugin_options.0,
+                    plugin_options.1,
+                    parameters.0,
+                    parameters.1,
+                    artifacts.0,
+                    artifacts.1,
+                ),
+            )
+            .await?;
+
+        let mut area = [0u8; RESULT_SIZE];
+        self.memory
+            .read(&mut *store, result_ptr as usize, &mut area)
+            .context("Reading result of module")?;
+        match ResultArea::parse(&area)? {
+            ResultArea::Ok {
+                phase,
+                message,
+                artifacts,
+                parameters,
+            } => Ok(PluginResult {
+                phase,
+                message: self.lift_string(store, message).await?,
+                outputs: Outputs {
+                    artifacts: self.lift_artifacts(store, artifacts).await?,
+                    parameters: self.lift_parameters(store, parameters).await?,
+                },
+                progress: None,
+            }),
+            ResultArea::Err(message) => Ok(PluginResult {
+                phase: Phase::Failed,
+                message: self.lift_string(store, message).await?,
+                outputs: Outputs::default(),
+                progress: None,
+            }),
+        }
+    }
+
+    async fn alloc(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        align: u32,
+        size: u32,
+    ) -> anyhow::Result<u32> {
+        self.realloc
+            .call_async(&mut *store, (0, 0, align, size))
+            .await
+    }
+
+    async fn lower_string(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        s: &str,
+    ) -> anyhow::Result<(u32, u32)> {
+        let ptr = self.alloc(store, 1, s.len() as u32).await?;
+        self.memory.write(&mut *store, ptr as usize, s.as_bytes())?;
+        Ok((ptr, s.len() as u32))
+    }
+
+    /// Lowers a list of records that consist of two strings
+    async fn lower_string_pairs(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        pairs: &[(&str, String)],
+    ) -> anyhow::Result<(u32, u32)> {
+        let mut records: Vec<u8> = Vec::with_capacity(pairs.len() * RECORD_SIZE as usize);
+        for (first, second) in pairs {
+            let first = self.lower_string(store, first).await?;
+            let second = self.lower_string(store, second).await?;
+            for field in [first.0, first.1, second.0, second.1] {
+                records.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        let ptr = self
+            .alloc(store, RECORD_ALIGN, records.len() as u32)
+            .await?;
+        self.memory.write(&mut *store, ptr as usize, &records)?;
+        Ok((ptr, pairs.len() as u32))
+    }
+
+    async fn lower_parameters(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        parameters: &[Parameter],
+    ) -> anyhow::Result<(u32, u32)> {
+        let pairs = parameters
+            .iter()
+            .map(|parameter| (parameter.name.as_str(), parameter.value.to_string()))
+            .collect::<Vec<_>>();
+        self.lower_string_pairs(store, &pairs).await
+    }
+
+    async fn lower_artifacts(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        artifacts: &[ArtifactRef],
+    ) -> anyhow::Result<(u32, u32)> {
+        let pairs = artifacts
+            .iter()
+            .map(|artifact| (artifact.name.as_str(), artifact.path.to_owned()))
+            .collect::<Vec<_>>();
+        self.lower_string_pairs(store, &pairs).await
+    }
+
+    /// Reads a string returned by the module and frees it
+    async fn lift_string(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        (ptr, len): (u32, u32),
+    ) -> anyhow::Result<String> {
+        let buf = guest_memory::read(&self.memory, &*store, ptr, len)
+            .ok_or_else(|| anyhow!("Module returned a string outside of its memory"))?;
+        self.free.call_async(&mut *store, (ptr, len, 1)).await?;
+        String::from_utf8(buf).context("Module returned a string that is not valid UTF-8")
+    }
+
+    /// Reads a list of records that consist of two strings returned by the module and frees it
+    async fn lift_string_pairs(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        (ptr, len): (u32, u32),
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let size = len
+            .checked_mul(RECORD_SIZE)
+            .ok_or_else(|| anyhow!(format!("Module returned a list of {} records", len)))?;
+        let records = guest_memory::read(&self.memory, &*store, ptr, size)
+            .ok_or_else(|| anyhow!("Module returned a list outside of its memory"))?;
+        let mut pairs = Vec::with_capacity(len as usize);
+        for record in records.chunks_exact(RECORD_SIZE as usize) {
+            let first = (read_u32(record, 0), read_u32(record, 4));
+            let second = (read_u32(record, 8), read_u32(record, 12));
+            pairs.push((
+                self.lift_string(store, first).await?,
+                self.lift_string(store, second).await?,
+            ));
+        }
+        self.free
+            .call_async(&mut *store, (ptr, size, RECORD_ALIGN))
+            .await?;
+        Ok(pairs)
+    }
+
+    async fn lift_parameters(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        list: (u32, u32),
+    ) -> anyhow::Result<Vec<Parameter>> {
+        self.lift_string_pairs(store, list)
+            .await?
+            .into_iter()
+            .map(|(name, value)| parse_parameter(name, value))
+            .collect()
+    }
+
+    async fn lift_artifacts(
+        &self,
+        store: &mut Store<ModuleCtx>,
+        list: (u32, u32),
+    ) -> anyhow::Result<Vec<ArtifactRef>> {
+        Ok(self
+            .lift_string_pairs(store, list)
+            .await?
+            .into_iter()
+            .map(|(name, path)| ArtifactRef {
+                name,
+                path,
+                s3: None,
+            })
+            .collect())
+    }
+}
+
+/// ResultArea is the `result<plugin-result, string>` returned by `run`, with strings and lists
+/// as pointer and length
+#[derive(Debug, PartialEq)]
+enum ResultArea {
+    Ok {
+        phase: Phase,
+        message: (u32, u32),
+        artifacts: (u32, u32),
+        parameters: (u32, u32),
+    },
+    Err((u32, u32)),
+}
+
+impl ResultArea {
+    fn parse(area: &[u8; RESULT_SIZE]) -> anyhow::Result<Self> {
+        match area[0] {
+            0 => Ok(ResultArea::Ok {
+                phase: match area[4] {
+                    0 => Phase::Succeeded,
+                    1 => Phase::Failed,
+                    phase => {
+                        return Err(anyhow!(format!("Module returned unknown phase {}", phase)))
+                    }
+                },
+                message: (read_u32(area, 8), read_u32(area, 12)),
+                artifacts: (read_u32(area, 16), read_u32(area, 20)),
+                parameters: (read_u32(area, 24), read_u32(area, 28)),
+            }),
+            1 => Ok(ResultArea::Err((read_u32(area, 4), read_u32(area, 8)))),
+            discriminant => Err(anyhow!(format!(
+                "Module returned unknown result discriminant {}",
+                discriminant
+            ))),
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::local::wit::{ResultArea, RESULT_SIZE};
+    use workflow_model::model::Phase;
+
+    fn area(words: [u32; 8]) -> [u8; RESULT_SIZE] {
+        let mut area = [0u8; RESULT_SIZE];
+        for (i, word) in words.iter().enumerate() {
+            area[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        area
+    }
+
+    #[test]
+    fn test_parse_result_area() {
+        assert_eq!(
+            ResultArea::Ok {
+                phase: Phase::Failed,
+                message: (100, 5),
+                artifacts: (200, 1),
+                parameters: (300, 2),
+            },
+            ResultArea::parse(&area([0, 1, 100, 5, 200, 1, 300, 2])).unwrap()
+        );
+        assert_eq!(
+            ResultArea::Err((100, 5)),
+            ResultArea::parse(&area([1, 100, 5, 0, 0, 0, 0, 0])).unwrap()
+        );
+        assert!(ResultArea::parse(&area([0, 2, 100, 5, 0, 0, 0, 0])).is_err());
+        assert!(ResultArea::parse(&area([2, 0, 0, 0, 0, 0, 0, 0])).is_err());
+    }
+}
@@ -1,6 +1,8 @@
-use crate::app::model::ModulePermissions;
-use anyhow::Error;
+use crate::app::model::{ModuleLimits, ModuleLogs, WasmPluginConfig};
+use anyhow::{anyhow, Error};
 use async_trait::async_trait;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
 use workflow_model::model::{PluginInvocation, PluginResult, S3ArtifactRepositoryConfig};
 
 pub mod distributed;
@@ -10,11 +12,16 @@ pub mod local;
 pub trait Runner {
     async fn run(
         &self,
-        oci_image: &str,
         invocation: PluginInvocation,
-        perms: &Option<ModulePermissions>,
+        config: &WasmPluginConfig,
         artifact_repo_config: Option<S3ArtifactRepositoryConfig>,
     ) -> anyhow::Result<PluginResult, WasmError>;
+
+    /// Pulls, verifies and precompiles the OCI image of a module ahead of its first run. Runners
+    /// that do not keep modules ignore it.
+    async fn prefetch(&self, _image: &str) -> anyhow::Result<(), WasmError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -25,4 +32,162 @@ pub enum WasmError {
     Invocation(Error),
     OutputProcessing(Error),
     Timeout(Error),
+    ResourceExhausted(Error),
+    Rejected(Error),
+}
+
+impl WasmError {
+    pub fn error(&self) -> &Error {
+        match self {
+            WasmError::EnvironmentSetup(err)
+            | WasmError::Retrieve(err)
+            | WasmError::Precompile(err)
+            | WasmError::Invocation(err)
+            | WasmError::OutputProcessing(err)
+            | WasmError::Timeout(err)
+            | WasmError::ResourceExhausted(err)
+            | WasmError::Rejected(err) => err,
+        }
+    }
+}
+
+/// ExecutionLimits are the effective limits of a single module execution
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionLimits {
+    pub fuel: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub memory: Option<usize>,
+    pub table_elements: Option<u32>,
+    pub instances: Option<usize>,
+}
+
+impl ExecutionLimits {
+    /// Returns the limits set in the template, falling back to self for any limit that is unset
+    pub fn merge(&self, template: &Option<ModuleLimits>) -> Self {
+        let mut limits = self.clone();
+        if let Some(template) = template {
+            if let Some(fuel) = template.fuel {
+                limits.fuel = Some(fuel);
+            }
+            if let Some(timeout_seconds) = template.timeout_seconds {
+                limits.timeout = Some(Duration::from_secs(timeout_seconds));
+            }
+            if let Some(memory) = template.memory {
+                limits.memory = Some(memory.bytes() as usize);
+            }
+            if let Some(table_elements) = template.table_elements {
+                limits.table_elements = Some(table_elements);
+            }
+            if let Some(instances) = template.instances {
+                limits.instances = Some(instances);
+            }
+        }
+        limits
+    }
+}
+
+/// LogOptions decide what happens to the stdout and stderr and the HTTP requests of a module
+/// besides being traced
+#[derive(Clone, Debug, Default)]
+pub struct LogOptions {
+    pub parameters: bool,
+    pub artifact: bool,
+    pub http_calls: bool,
+    /// Maximum number of bytes kept of each stream
+    pub max_size: usize,
+}
+
+impl LogOptions {
+    pub fn new(template: &Option<ModuleLogs>, max_size: usize) -> Self {
+        match template {
+            Some(logs) => LogOptions {
+                parameters: logs.parameters,
+                artifact: logs.artifact,
+                http_calls: logs.http_calls,
+                max_size,
+            },
+            None => LogOptions {
+                max_size,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Progress is the last progress a module reported. It is attached as context to the errors of
+/// modules that failed after they started, so that it is reported even though the module has no
+/// result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub done: u32,
+    pub total: u32,
+    pub message: String,
+}
+
+impl Progress {
+    /// Returns the progress in the format of Argo, `N/M`
+    pub fn fraction(&self) -> String {
+        format!("{}/{}", self.done, self.total)
+    }
+
+    /// Returns the progress of the error, if it has one attached
+    pub fn of(err: &Error) -> Option<String> {
+        err.downcast_ref::<Progress>().map(Progress::fraction)
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.message.is_empty() {
+            true => write!(f, "Last reported progress {}", self.fraction()),
+            false => write!(
+                f,
+                "Last reported progress {}: {}",
+                self.fraction(),
+                self.message
+            ),
+        }
+    }
+}
+
+/// Returns the value of the input parameter with the given name. String values are returned as
+/// is, other values as JSON.
+pub fn parameter_value(invocation: &PluginInvocation, name: &str) -> anyhow::Result<String> {
+    let parameter = invocation
+        .parameters
+        .iter()
+        .find(|parameter| parameter.name == name)
+        .ok_or_else(|| anyhow!(format!("Input parameter {} not found", name)))?;
+    Ok(match &parameter.value {
+        serde_json::Value::String(value) => value.to_owned(),
+        value => value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::wasm::parameter_value;
+    use workflow_model::model::{Parameter, PluginInvocation};
+
+    #[test]
+    fn test_parameter_value() {
+        let invocation = PluginInvocation {
+            workflow_name: "test".to_string(),
+            plugin_options: vec![],
+            parameters: vec![
+                Parameter {
+                    name: "text".to_string(),
+                    value: serde_json::json!("hello"),
+                },
+                Parameter {
+                    name: "count".to_string(),
+                    value: serde_json::json!(3),
+                },
+            ],
+            artifacts: vec![],
+        };
+        assert_eq!("hello", parameter_value(&invocation, "text").unwrap());
+        assert_eq!("3", parameter_value(&invocation, "count").unwrap());
+        assert!(parameter_value(&invocation, "missing").is_err());
+    }
 }
@@ -1,35 +1,36 @@
 use crate::app::dependencies::DynDependencyProvider;
-use crate::app::model::ModuleSource::OCI;
 use crate::app::model::{ExecuteTemplateRequest, ExecuteTemplateResponse, ExecuteTemplateResult};
-use crate::app::wasm::WasmError;
+use crate::app::wasm::{Progress, WasmError};
 use axum::extract::Extension;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use axum_macros::debug_handler;
-use tracing::{debug, error, Instrument};
+use tracing::{debug, error, warn, Instrument};
 use workflow_model::model::{ArtifactRef, Parameter, Phase, PluginInvocation};
 
 #[debug_handler]
-#[tracing::instrument(name = "request.execute_template", fields(workflow_name=&request.workflow.metadata.name.as_str()), skip(deps))]
+#[tracing::instrument(name = "request.execute_template", fields(workflow_name=&request.workflow.metadata.name.as_str(), template_name=&request.template.name.as_str()), skip(deps))]
 pub async fn execute_template(
     Json(request): Json<ExecuteTemplateRequest>,
     Extension(deps): Extension<DynDependencyProvider>,
 ) -> Result<Json<ExecuteTemplateResponse>, AppError> {
-    debug!("Request: {:?}", request);
-
-    let (module_source, permissions, plugin_options_map) = match request.template.plugin.wasm {
-        Some(config) => (config.module, config.permissions, config.extra),
+    let mut config = match request.template.plugin.wasm {
+        Some(config) => config,
         None => return Ok(ExecuteTemplateResponse { node: None }.into()),
     };
 
-    let plugin_options: Vec<Parameter> = plugin_options_map
+    for key in config.former_plugin_options() {
+        warn!(
+            "Template option {} is read by the plugin and not passed to the module as plugin option",
+            key
+        );
+    }
+    let plugin_options: Vec<Parameter> = std::mem::take(&mut config.extra)
         .into_iter()
         .map(|(name, value)| Parameter { name, value })
         .collect();
 
-    let OCI(image) = module_source;
-
     let mut in_params: Vec<Parameter> = Vec::new();
     if let Some(params) = request.template.inputs.parameters {
         in_params = params;
@@ -58,9 +59,7 @@ pub async fn execute_template(
         async move {
             let runner = deps.get_runner();
             let artifact_repo_config = deps.get_artifact_repository_config();
-            runner
-                .run(&image, invocation, &permissions, artifact_repo_config)
-                .await
+            runner.run(invocation, &config, artifact_repo_config).await
         }
         .instrument(span),
     )
@@ -70,8 +69,9 @@ pub async fn execute_template(
     match result {
         Ok(result) => {
             let result = ExecuteTemplateResult::from_plugin_result(result);
+            // The outputs and message come from the module and may contain secrets
+            debug!(phase = ?result.phase, progress = ?result.progress, "Send Response");
             let response = ExecuteTemplateResponse { node: Some(result) };
-            debug!(?response, "Send Response");
             Ok(response.into())
         }
         Err(err) => {
@@ -81,6 +81,22 @@ pub async fn execute_template(
     }
 }
 
+/// Reports whether the plugin is ready to run modules, which it is once it prefetched them. The
+/// liveness probe `/healthz` succeeds as soon as the server runs.
+pub async fn ready(
+    Extension(deps): Extension<DynDependencyProvider>,
+) -> (StatusCode, &'static str) {
+    match deps.is_ready() {
+        true => (StatusCode::OK, "ok\n"),
+        false => (StatusCode::SERVICE_UNAVAILABLE, "prefetching modules\n"),
+    }
+}
+
+/// Serves the metrics of the plugin in the Prometheus text format
+pub async fn metrics(Extension(deps): Extension<DynDependencyProvider>) -> String {
+    deps.get_metrics().render()
+}
+
 pub enum AppError {
     ModuleExecution(WasmError),
 }
@@ -93,38 +109,59 @@ impl From<WasmError> for AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        // The node keeps the progress the module reported before it failed
+        let progress = match &self {
+            AppError::ModuleExecution(err) => Progress::of(err.error()),
+        };
         let (status, error_message) = match self {
             AppError::ModuleExecution(WasmError::EnvironmentSetup(_err)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Wasm environment is not set up correctly",
+                "Wasm environment is not set up correctly".to_string(),
             ),
             AppError::ModuleExecution(WasmError::Invocation(_err)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Wasm module invocation failed",
+                "Wasm module invocation failed".to_string(),
             ),
             AppError::ModuleExecution(WasmError::OutputProcessing(_err)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Wasm module output processing failed",
+                "Wasm module output processing failed".to_string(),
             ),
             AppError::ModuleExecution(WasmError::Retrieve(_err)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Wasm module could not be retrieved",
+                "Wasm module could not be retrieved".to_string(),
             ),
             AppError::ModuleExecution(WasmError::Precompile(_)) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Wasm module could not be precompiled",
+                "Wasm module could not be precompiled".to_string(),
             ),
-            AppError::ModuleExecution(WasmError::Timeout(_)) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Wasm module did not report result in time (timeout)",
+            // Exceeding a limit is a failure of the module, not of the plugin, so the node fails
+            // with a regular response
+            AppError::ModuleExecution(WasmError::Timeout(err)) => (
+                StatusCode::OK,
+                format!(
+                    "Wasm module did not report result in time (timeout): {}",
+                    err
+                ),
+            ),
+            AppError::ModuleExecution(WasmError::ResourceExhausted(err)) => (
+                StatusCode::OK,
+                format!("Wasm module exhausted its resources: {}", err),
+            ),
+            AppError::ModuleExecution(WasmError::Rejected(err)) => (
+                StatusCode::OK,
+                format!(
+                    "Wasm module was rejected by the signature policy: {:#}",
+                    err
+                ),
             ),
         };
 
         let response = Json(ExecuteTemplateResponse {
             node: Some(ExecuteTemplateResult {
                 phase: Phase::Failed,
-                message: error_message.to_string(),
+                message: error_message,
                 outputs: None,
+                progress,
             }),
         });
 
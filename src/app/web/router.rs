@@ -1,8 +1,9 @@
 use crate::app::dependencies::DynDependencyProvider;
 use crate::app::web::handler;
+use axum::extract::Extension;
 use axum::http::{header, HeaderValue};
 use axum::routing::{get, post};
-use axum::{AddExtensionLayer, Router};
+use axum::Router;
 use tower::limit::concurrency::ConcurrencyLimitLayer;
 use tower_http::set_header::SetRequestHeaderLayer;
 use tower_http::trace::TraceLayer;
@@ -11,7 +12,7 @@ pub fn routes(deps: DynDependencyProvider) -> axum::Router {
     let num_concurrent_requests = deps.get_config().num_concurrent_requests();
     let template_execute = post(handler::execute_template)
         // TODO remove SetRequestHeaderLayer once Argo sends correct Content-Type header
-        .layer(AddExtensionLayer::new(deps))
+        .layer(Extension(deps.clone()))
         .layer(SetRequestHeaderLayer::if_not_present(
             header::CONTENT_TYPE,
             HeaderValue::from_static("application/json"),
@@ -21,5 +22,10 @@ pub fn routes(deps: DynDependencyProvider) -> axum::Router {
 
     Router::new()
         .route("/healthz", get(|| async { "ok\n" }))
+        .route(
+            "/readyz",
+            get(handler::ready).layer(Extension(deps.clone())),
+        )
+        .route("/metrics", get(handler::metrics).layer(Extension(deps)))
         .route("/api/v1/template.execute", template_execute)
 }
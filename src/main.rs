@@ -1,7 +1,9 @@
 use crate::app::tracing as app_tracing;
+use crate::app::wasm::local::cache::{self, PurgePolicy};
 use anyhow::anyhow;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use std::time::Duration;
 use tokio::signal;
 
 pub mod app;
@@ -20,6 +22,28 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Log level is {}", log_level);
     tracing::info!("Mode is {}", config.mode());
 
+    if let Some(module_cache) = deps.get_module_cache() {
+        let policy = PurgePolicy {
+            max_size: config.fs_cache_max_size.map(|size| size.bytes()),
+            max_age: config.fs_cache_max_age.map(Duration::from_secs),
+        };
+        if !policy.is_unlimited() {
+            let interval = Duration::from_secs(config.fs_cache_purge_interval);
+            tracing::info!(?policy, ?interval, "Purging module cache in the background");
+            cache::spawn_purge(module_cache, policy, interval);
+        }
+    }
+
+    // The server starts right away, but only reports ready once the modules are prefetched
+    let prefetch_deps = deps.clone();
+    tokio::spawn(async move {
+        let modules = prefetch_deps.get_prefetch_modules();
+        if !modules.is_empty() {
+            app::prefetch::prefetch_modules(prefetch_deps.get_runner(), modules).await;
+        }
+        prefetch_deps.set_ready();
+    });
+
     let bind_ip = config.bind_ip.clone();
     let bind_port = config.bind_port;
 
@@ -61,6 +61,7 @@ fn run(invocation: PluginInvocation, _: ArtifactManager) -> anyhow::Result<Plugi
             artifacts: vec![],
             parameters,
         },
+        progress: None,
     })
 }
 
@@ -99,6 +99,7 @@ fn run(
             artifacts: vec![output],
             ..Default::default()
         },
+        progress: None,
     })
 }
 
@@ -32,5 +32,6 @@ fn run(invocation: PluginInvocation, _artifacts: ArtifactManager) -> anyhow::Res
                 value: output_text.into(),
             }],
         },
+        progress: None,
     })
 }
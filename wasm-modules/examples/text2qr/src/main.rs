@@ -33,5 +33,6 @@ fn run(invocation: PluginInvocation, _artifacts: ArtifactManager) -> anyhow::Res
                 value: image.into(),
             }],
         },
+        progress: None,
     })
 }
@@ -18,5 +18,6 @@ fn run(invocation: PluginInvocation, _: ArtifactManager) -> anyhow::Result<Plugi
                 value: "Schnapspraline?".into(),
             }],
         },
+        progress: None,
     })
 }